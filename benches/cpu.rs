@@ -0,0 +1,84 @@
+use chip_8::config::DEFAULT_IPF;
+use chip_8::cpu::{Processor, CPU};
+use chip_8::rng::{Rng, RngModel};
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::fs;
+use std::path::Path;
+
+const TURBO_IPF: u32 = 10_000;
+
+const ROMS: &[(&str, &str)] = &[
+    ("maze", "Maze [David Winter, 199x].ch8"),
+    ("stars", "Stars [Sergey Naydenov, 2010].ch8"),
+    ("trip8", "Trip8 Demo (2008) [Revival Studios].ch8"),
+    ("15_puzzle", "15 Puzzle [Roger Ivie] (alt).ch8"),
+];
+
+/// The plain interpreter, and the one with the decode cache.
+const ENGINES: &[(&str, bool)] = &[("interpreter", false), ("cached", true)];
+
+fn cpu_with(rom: &[u8], decode_cache: bool) -> CPU {
+    let mut cpu = CPU::new();
+    cpu.set_decode_cache(decode_cache);
+    cpu.rng = Rng::new(RngModel::Xorshift, 0);
+    cpu.load_rom(rom);
+    cpu
+}
+
+fn tick(c: &mut Criterion) {
+    // a loop of arithmetic: LD V0, 1; ADD V1, 1; ADD V0, V1; SE V0, 0; JMP 0x200
+    let rom = [0x60, 0x01, 0x71, 0x01, 0x80, 0x14, 0x30, 0x00, 0x12, 0x00];
+    let mut group = c.benchmark_group("tick");
+    for (engine, decode_cache) in ENGINES {
+        let mut cpu = cpu_with(&rom, *decode_cache);
+        group.bench_function(*engine, |b| b.iter(|| cpu.tick()));
+    }
+    group.finish();
+}
+
+fn draw(c: &mut Criterion) {
+    // 0x200: LD I, 0; 0x202: DRW V0, V1, 15; 0x204: ADD V0, 3; 0x206: JMP 0x202
+    let mut cpu = cpu_with(&[0xa0, 0x00, 0xd0, 0x1f, 0x70, 0x03, 0x12, 0x02], false);
+    cpu.tick();
+    // draw, add, jump
+    c.bench_function("dxyn", |b| {
+        b.iter(|| {
+            cpu.tick();
+            cpu.tick();
+            cpu.tick();
+        })
+    });
+}
+
+fn frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    for (name, file) in ROMS {
+        let rom = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join(file)).unwrap();
+        for (engine, decode_cache) in ENGINES {
+            let mut cpu = cpu_with(&rom, *decode_cache);
+            group.bench_function(format!("{}/{}", name, engine), |b| {
+                b.iter(|| cpu.run_frame(DEFAULT_IPF))
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Frames in turbo: the speed at which the engines matter most.
+fn turbo_frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("turbo_frame");
+    let rom = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join(ROMS[2].1)).unwrap();
+    for (engine, decode_cache) in ENGINES {
+        let mut cpu = cpu_with(&rom, *decode_cache);
+        group.bench_function(*engine, |b| b.iter(|| cpu.run_frame(TURBO_IPF)));
+    }
+    #[cfg(feature = "recompiler")]
+    {
+        let mut recompiler = chip_8::recompiler::Recompiler::new(cpu_with(&rom, false));
+        group.bench_function("recompiler", |b| b.iter(|| recompiler.run_frame(TURBO_IPF)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tick, draw, frame, turbo_frame);
+criterion_main!(benches);
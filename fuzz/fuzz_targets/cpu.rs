@@ -0,0 +1,46 @@
+#![no_main]
+use chip_8::cpu::{Processor, CPU};
+use chip_8::platform::Platform;
+use chip_8::quirks::Quirks;
+use chip_8::timing::Timing;
+use libfuzzer_sys::fuzz_target;
+
+const IPF: usize = 10;
+// frames run after the scripted input, so that short inputs still run the rom for a while.
+const EXTRA_FRAMES: usize = 60;
+
+// The first byte gives the number of keypad bytes that follow, one per frame: the low nibble is
+// the key, the high bit whether it is pressed. The second byte selects the platform with its low
+// bits and the VIP timing with its high bit. The rest of the input is the rom.
+fuzz_target!(|data: &[u8]| {
+    let (header, machine, data) = match data {
+        [header, machine, data @ ..] => (*header, *machine, data),
+        _ => return,
+    };
+    let (keys, rom) = data.split_at((header as usize & 0x7f).min(data.len()));
+    let platforms = Platform::all();
+    let platform = platforms[(machine & 0x7f) as usize % platforms.len()];
+    let mut cpu = CPU::with_quirks(Quirks {
+        display_wait: header & 0x80 != 0,
+    });
+    cpu.set_platform(platform);
+    if machine & 0x80 != 0 {
+        cpu.timing = Timing::Vip;
+    }
+    cpu.load_rom(rom);
+
+    let frames = keys.iter().map(Some).chain((0..EXTRA_FRAMES).map(|_| None));
+    for key in frames {
+        if let Some(&key) = key {
+            cpu.set_key_press(key & 0xf, key & 0x80 != 0);
+        }
+        for _ in 0..IPF {
+            cpu.tick();
+            assert!(cpu.pc < cpu.ram.len(), "pc out of bounds: 0x{:x}", cpu.pc);
+            assert!(cpu.stack.len() <= 16, "stack overflow");
+            assert_eq!(cpu.vram.rows().len(), platform.display_height());
+        }
+        cpu.tick_timers();
+        cpu.vblank();
+    }
+});
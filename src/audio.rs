@@ -0,0 +1,444 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use v_display::sdl2::audio::AudioCallback;
+
+// time the envelope takes to go from silence to full volume and back, in seconds.
+const RAMP_TIME: f32 = 0.005;
+const FRAME_RATE: f64 = 60.0;
+const QUEUE_CAPACITY: usize = 16;
+// frames queued before playback starts again after running dry.
+const PRIME_FRAMES: usize = 2;
+// what the slots of the queue hold.
+const SILENT: u8 = 0;
+const BEEP: u8 = 1;
+const SAMPLES: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub frequency: f32,
+    pub volume: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Self {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+impl Tone {
+    // the volume between 0 and 1, and the frequency between 1Hz and half the sample rate, above
+    // which it would alias.
+    fn clamped(self, sample_rate: f32) -> Self {
+        Self {
+            frequency: self.frequency.max(1.0).min(sample_rate / 2.0),
+            volume: if self.volume.is_nan() {
+                0.0
+            } else {
+                self.volume.clamp(0.0, 1.0)
+            },
+            ..self
+        }
+    }
+}
+
+/// Band-limited oscillator producing the beep, gated by the sound timer.
+pub struct Beeper {
+    tone: Tone,
+    sample_rate: f32,
+    phase: f32,
+    phase_inc: f32,
+    // state of the leaky integrator turning the square into a triangle.
+    integrator: f32,
+    gain: f32,
+    gain_step: f32,
+    gate: bool,
+    muted: bool,
+}
+
+impl Beeper {
+    pub fn new(tone: Tone, sample_rate: i32) -> Self {
+        let sample_rate = sample_rate as f32;
+        let tone = tone.clamped(sample_rate);
+        Self {
+            tone,
+            sample_rate,
+            phase: 0.0,
+            phase_inc: tone.frequency / sample_rate,
+            integrator: 0.0,
+            gain: 0.0,
+            gain_step: 1.0 / (RAMP_TIME * sample_rate),
+            gate: false,
+            muted: false,
+        }
+    }
+
+    /// Changes the tone, with its volume and frequency brought in range.
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone.clamped(self.sample_rate);
+        self.phase_inc = self.tone.frequency / self.sample_rate;
+    }
+
+    /// Starts or stops the beep. The volume is ramped to avoid clicks.
+    pub fn set_gate(&mut self, gate: bool) {
+        self.gate = gate;
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// The volume of the digitised sounds played along.
+    pub fn volume(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.tone.volume
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let target = if self.gate && !self.muted { 1.0 } else { 0.0 };
+        if self.gain < target {
+            self.gain = (self.gain + self.gain_step).min(target);
+        } else if self.gain > target {
+            self.gain = (self.gain - self.gain_step).max(target);
+        }
+        if self.gain == 0.0 {
+            // restart the waveform from the same point on each beep.
+            self.phase = 0.0;
+            self.integrator = 0.0;
+            return 0.0;
+        }
+
+        let sample = match self.tone.waveform {
+            Waveform::Square => self.square(),
+            Waveform::Sine => (2.0 * PI * self.phase).sin(),
+            Waveform::Triangle => {
+                self.integrator =
+                    self.phase_inc * self.square() + (1.0 - self.phase_inc) * self.integrator;
+                self.integrator * 4.0
+            }
+        };
+        self.phase = (self.phase + self.phase_inc) % 1.0;
+        sample * self.tone.volume * self.gain
+    }
+
+    // square wave with its discontinuities smoothed by polyBLEP, which removes most of the
+    // aliasing.
+    fn square(&self) -> f32 {
+        let naive = if self.phase < 0.5 { 1.0 } else { -1.0 };
+        naive + poly_blep(self.phase, self.phase_inc)
+            - poly_blep((self.phase + 0.5) % 1.0, self.phase_inc)
+    }
+}
+
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// The sound of a 60Hz frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameSound {
+    /// Whether the beeper sounds.
+    Beep(bool),
+    /// A digitised sound, spread evenly over the frame.
+    Samples(Vec<f32>),
+}
+
+/// The digitised sound at `position` in a frame, from 0 to 1.
+pub fn resample(samples: &[f32], position: f64) -> f32 {
+    match samples.len() {
+        0 => 0.0,
+        len => samples[((position * len as f64) as usize).min(len - 1)],
+    }
+}
+
+/// Lock-free single producer, single consumer queue of the beeper state of each 60Hz frame.
+/// The frames of digitised sound are passed aside, with a lock.
+#[derive(Clone)]
+pub struct SoundQueue {
+    inner: Arc<QueueInner>,
+}
+
+struct QueueInner {
+    frames: Vec<AtomicU8>,
+    samples: Mutex<VecDeque<Vec<f32>>>,
+    // total number of frames read and written, the slot is the count modulo the capacity.
+    read: AtomicUsize,
+    written: AtomicUsize,
+}
+
+impl SoundQueue {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(QueueInner {
+                frames: (0..QUEUE_CAPACITY).map(|_| AtomicU8::new(SILENT)).collect(),
+                samples: Mutex::new(VecDeque::new()),
+                read: AtomicUsize::new(0),
+                written: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Queues the state of the next frame, returns false if the queue is full.
+    pub fn push(&self, beep: bool) -> bool {
+        self.push_frame(if beep { BEEP } else { SILENT }, None)
+    }
+
+    /// Queues a frame of digitised sound, returns false if the queue is full.
+    pub fn push_samples(&self, samples: Vec<f32>) -> bool {
+        self.push_frame(SAMPLES, Some(samples))
+    }
+
+    fn push_frame(&self, frame: u8, samples: Option<Vec<f32>>) -> bool {
+        let written = self.inner.written.load(Ordering::Relaxed);
+        if written - self.inner.read.load(Ordering::Acquire) == QUEUE_CAPACITY {
+            return false;
+        }
+        // the samples are there before the frame is.
+        if let Some(samples) = samples {
+            self.inner.samples.lock().unwrap().push_back(samples);
+        }
+        self.inner.frames[written % QUEUE_CAPACITY].store(frame, Ordering::Relaxed);
+        self.inner.written.store(written + 1, Ordering::Release);
+        true
+    }
+
+    pub fn pop(&self) -> Option<FrameSound> {
+        let read = self.inner.read.load(Ordering::Relaxed);
+        if read == self.inner.written.load(Ordering::Acquire) {
+            return None;
+        }
+        let sound = match self.inner.frames[read % QUEUE_CAPACITY].load(Ordering::Relaxed) {
+            SAMPLES => {
+                let samples = self.inner.samples.lock().unwrap().pop_front();
+                FrameSound::Samples(samples.unwrap_or_default())
+            }
+            frame => FrameSound::Beep(frame == BEEP),
+        };
+        self.inner.read.store(read + 1, Ordering::Release);
+        Some(sound)
+    }
+
+    pub fn len(&self) -> usize {
+        let read = self.inner.read.load(Ordering::Acquire);
+        self.inner.written.load(Ordering::Acquire) - read
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for SoundQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Audio callback playing the frames of a `SoundQueue`, each for exactly 1/60s of samples.
+pub struct QueuedBeeper {
+    pub beeper: Beeper,
+    queue: SoundQueue,
+    samples_per_frame: f64,
+    // samples left to play for the current frame, and its digitised sound.
+    remaining: f64,
+    digitised: Vec<f32>,
+    primed: bool,
+}
+
+impl QueuedBeeper {
+    pub fn new(beeper: Beeper, queue: SoundQueue, sample_rate: i32) -> Self {
+        Self {
+            beeper,
+            queue,
+            samples_per_frame: sample_rate as f64 / FRAME_RATE,
+            remaining: 0.0,
+            digitised: Vec::new(),
+            primed: false,
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        if self.remaining < 1.0 {
+            if !self.primed && self.queue.len() >= PRIME_FRAMES {
+                self.primed = true;
+            }
+            let next = if self.primed { self.queue.pop() } else { None };
+            match next {
+                Some(sound) => {
+                    match sound {
+                        FrameSound::Beep(beep) => {
+                            self.beeper.set_gate(beep);
+                            self.digitised.clear();
+                        }
+                        FrameSound::Samples(samples) => {
+                            self.beeper.set_gate(false);
+                            self.digitised = samples;
+                        }
+                    }
+                    self.remaining += self.samples_per_frame;
+                }
+                None => {
+                    // the emulation is late or paused: stay silent until it catches up.
+                    self.primed = false;
+                    self.beeper.set_gate(false);
+                    self.digitised.clear();
+                    self.remaining = 1.0;
+                }
+            }
+        }
+        let position = 1.0 - self.remaining / self.samples_per_frame;
+        self.remaining -= 1.0;
+        self.beeper.next_sample()
+            + resample(&self.digitised, position.max(0.0)) * self.beeper.volume()
+    }
+}
+
+impl AudioCallback for QueuedBeeper {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [Self::Channel]) {
+        for x in out.iter_mut() {
+            *x = self.next_sample();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beeper_ramps() {
+        let mut beeper = Beeper::new(Tone::default(), 44100);
+        assert_eq!(beeper.next_sample(), 0.0);
+        beeper.set_gate(true);
+        // no click: the first samples stay well below the full volume.
+        assert!(beeper.next_sample().abs() < 0.01);
+        let ramp = (RAMP_TIME * 44100.0) as usize + 1;
+        let peak = (0..ramp * 4)
+            .map(|_| beeper.next_sample().abs())
+            .fold(0.0, f32::max);
+        assert!(peak > 0.2 && peak <= 0.3);
+        beeper.set_gate(false);
+        for _ in 0..ramp {
+            beeper.next_sample();
+        }
+        assert_eq!(beeper.next_sample(), 0.0);
+    }
+
+    #[test]
+    fn test_tone_clamped() {
+        let tone = Tone {
+            frequency: 30000.0,
+            volume: 4.0,
+            waveform: Waveform::Sine,
+        };
+        let mut beeper = Beeper::new(tone, 44100);
+        assert_eq!(beeper.tone.frequency, 22050.0);
+        assert_eq!(beeper.tone.volume, 1.0);
+        beeper.set_tone(Tone {
+            frequency: -5.0,
+            volume: f32::NAN,
+            ..tone
+        });
+        assert_eq!(beeper.tone.frequency, 1.0);
+        assert_eq!(beeper.tone.volume, 0.0);
+    }
+
+    #[test]
+    fn test_beeper_muted() {
+        for &waveform in &[Waveform::Square, Waveform::Sine, Waveform::Triangle] {
+            let tone = Tone {
+                waveform,
+                ..Tone::default()
+            };
+            let mut beeper = Beeper::new(tone, 44100);
+            beeper.set_gate(true);
+            beeper.set_muted(true);
+            assert!((0..1000).all(|_| beeper.next_sample() == 0.0));
+        }
+    }
+
+    #[test]
+    fn test_sound_queue() {
+        let queue = SoundQueue::new();
+        assert_eq!(queue.pop(), None);
+        for i in 0..QUEUE_CAPACITY {
+            assert!(queue.push(i % 2 == 0));
+        }
+        assert!(!queue.push(true));
+        assert!(!queue.push_samples(vec![0.5]));
+        assert_eq!(queue.len(), QUEUE_CAPACITY);
+        for i in 0..QUEUE_CAPACITY {
+            assert_eq!(queue.pop(), Some(FrameSound::Beep(i % 2 == 0)));
+        }
+        assert!(queue.is_empty());
+
+        queue.push_samples(vec![0.5]);
+        queue.push(true);
+        assert_eq!(queue.pop(), Some(FrameSound::Samples(vec![0.5])));
+        assert_eq!(queue.pop(), Some(FrameSound::Beep(true)));
+    }
+
+    #[test]
+    fn test_queued_samples() {
+        let queue = SoundQueue::new();
+        let mut player =
+            QueuedBeeper::new(Beeper::new(Tone::default(), 48000), queue.clone(), 48000);
+        // 4 samples stretched over the 800 of a frame.
+        queue.push_samples(vec![1.0, -1.0, 0.5, 0.0]);
+        queue.push(false);
+        let samples: Vec<f32> = (0..800).map(|_| player.next_sample()).collect();
+        assert_eq!(samples[0], 0.25);
+        assert_eq!(samples[199], 0.25);
+        assert_eq!(samples[200], -0.25);
+        assert_eq!(samples[500], 0.125);
+        assert_eq!(samples[799], 0.0);
+    }
+
+    #[test]
+    fn test_queued_beeper_timing() {
+        let queue = SoundQueue::new();
+        let mut player =
+            QueuedBeeper::new(Beeper::new(Tone::default(), 48000), queue.clone(), 48000);
+        // 800 samples per frame at 48kHz: one silent frame, three beeping, then silence.
+        for &beep in &[false, true, true, true, false, false] {
+            queue.push(beep);
+        }
+        let samples: Vec<f32> = (0..800 * 6).map(|_| player.next_sample()).collect();
+        let first = samples.iter().position(|s| *s != 0.0).unwrap();
+        let last = samples.iter().rposition(|s| *s != 0.0).unwrap();
+        let release = (RAMP_TIME * 48000.0) as usize;
+        assert!((800..=801).contains(&first));
+        assert!(last >= 800 * 4 && last <= 800 * 4 + release);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,436 @@
+/// What the 1802 is connected to: the memory, the I/O ports and the external flags.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+    /// INP 1-7, the value is stored in memory and in D.
+    fn input(&mut self, port: u8) -> u8;
+    /// OUT 1-7.
+    fn output(&mut self, port: u8, value: u8);
+    /// The EF1-4 input lines, tested by the branches.
+    fn flag(&self, flag: u8) -> bool;
+}
+
+/// The RCA CDP1802 COSMAC microprocessor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cdp1802 {
+    /// The 16 scratchpad registers, any of them can be the program counter (P) or the data
+    /// pointer (X).
+    pub r: [u16; 16],
+    pub p: usize,
+    pub x: usize,
+    pub d: u8,
+    pub df: bool,
+    /// Where X and P are saved on an interrupt.
+    pub t: u8,
+    pub ie: bool,
+    pub q: bool,
+    /// Stopped by IDL until the next interrupt or DMA.
+    pub idle: bool,
+}
+
+impl Default for Cdp1802 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cdp1802 {
+    /// The state after a reset: the program counter is R0, from address 0.
+    pub fn new() -> Self {
+        Self {
+            r: [0; 16],
+            p: 0,
+            x: 0,
+            d: 0,
+            df: false,
+            t: 0,
+            ie: true,
+            q: false,
+            idle: false,
+        }
+    }
+
+    /// Takes an interrupt if they are enabled, returns the machine cycles it took.
+    pub fn interrupt(&mut self) -> u32 {
+        if !self.ie {
+            return 0;
+        }
+        self.t = (self.x << 4 | self.p) as u8;
+        self.p = 1;
+        self.x = 2;
+        self.ie = false;
+        self.idle = false;
+        1
+    }
+
+    /// A DMA out cycle: reads the byte R0 points to, and moves R0 to the next one.
+    pub fn dma_out<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let value = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        value
+    }
+
+    /// Executes the next instruction, returns the machine cycles it took.
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> u32 {
+        if self.idle {
+            return 1;
+        }
+        let op = self.fetch(bus);
+        let n = (op & 0xf) as usize;
+        match op >> 4 {
+            0x0 if n == 0 => self.idle = true,
+            // LDN
+            0x0 => self.d = bus.read(self.r[n]),
+            // INC
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            // DEC
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            0x3 => {
+                let taken = self.condition(bus, n);
+                self.short_branch(bus, taken);
+            }
+            // LDA
+            0x4 => {
+                self.d = bus.read(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            }
+            // STR
+            0x5 => bus.write(self.r[n], self.d),
+            0x6 => self.io(bus, n),
+            0x7 => self.op_7n(bus, n),
+            // GLO
+            0x8 => self.d = self.r[n] as u8,
+            // GHI
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            // PLO
+            0xa => self.r[n] = self.r[n] & 0xff00 | self.d as u16,
+            // PHI
+            0xb => self.r[n] = self.r[n] & 0x00ff | (self.d as u16) << 8,
+            0xc => {
+                self.long_branch(bus, n);
+                return 3;
+            }
+            // SEP
+            0xd => self.p = n,
+            // SEX
+            0xe => self.x = n,
+            _ => self.op_fn(bus, n),
+        }
+        2
+    }
+
+    fn fetch<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let value = bus.read(self.r[self.p]);
+        self.r[self.p] = self.r[self.p].wrapping_add(1);
+        value
+    }
+
+    // The condition of the branches 3N and the long branches CN, negated by the bit 3.
+    fn condition<B: Bus>(&self, bus: &B, n: usize) -> bool {
+        let condition = match n & 7 {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            flag => bus.flag(flag as u8 - 3),
+        };
+        condition != (n & 8 != 0)
+    }
+
+    fn short_branch<B: Bus>(&mut self, bus: &mut B, taken: bool) {
+        let pc = self.r[self.p];
+        if taken {
+            let low = bus.read(pc);
+            self.r[self.p] = pc & 0xff00 | low as u16;
+        } else {
+            self.r[self.p] = pc.wrapping_add(1);
+        }
+    }
+
+    fn long_branch<B: Bus>(&mut self, bus: &mut B, n: usize) {
+        let pc = self.r[self.p];
+        if n & 4 == 0 {
+            // LBR, LBQ, LBZ, LBDF and their negations.
+            let condition = match n & 3 {
+                0 => true,
+                1 => self.q,
+                2 => self.d == 0,
+                _ => self.df,
+            };
+            if condition != (n & 8 != 0) {
+                let high = bus.read(pc);
+                let low = bus.read(pc.wrapping_add(1));
+                self.r[self.p] = (high as u16) << 8 | low as u16;
+            } else {
+                self.r[self.p] = pc.wrapping_add(2);
+            }
+        } else {
+            // NOP, LSNQ, LSNZ, LSNF, LSKP, LSIE, LSQ, LSZ and LSDF skip two bytes.
+            let skip = match n {
+                0x4 => false,
+                0x5 => !self.q,
+                0x6 => self.d != 0,
+                0x7 => !self.df,
+                0xc => self.ie,
+                0xd => self.q,
+                0xe => self.d == 0,
+                0xf => self.df,
+                _ => true,
+            };
+            if skip {
+                self.r[self.p] = pc.wrapping_add(2);
+            }
+        }
+    }
+
+    fn io<B: Bus>(&mut self, bus: &mut B, n: usize) {
+        let rx = self.r[self.x];
+        match n {
+            // IRX
+            0 => self.r[self.x] = rx.wrapping_add(1),
+            // OUT
+            1..=7 => {
+                let value = bus.read(rx);
+                bus.output(n as u8, value);
+                self.r[self.x] = rx.wrapping_add(1);
+            }
+            // unused on the 1802
+            8 => (),
+            // INP
+            _ => {
+                let value = bus.input(n as u8 - 8);
+                bus.write(rx, value);
+                self.d = value;
+            }
+        }
+    }
+
+    fn op_7n<B: Bus>(&mut self, bus: &mut B, n: usize) {
+        let rx = self.r[self.x];
+        match n {
+            // RET, DIS
+            0x0 | 0x1 => {
+                let xp = bus.read(rx);
+                self.r[self.x] = rx.wrapping_add(1);
+                self.x = (xp >> 4) as usize;
+                self.p = (xp & 0xf) as usize;
+                self.ie = n == 0;
+            }
+            // LDXA
+            0x2 => {
+                self.d = bus.read(rx);
+                self.r[self.x] = rx.wrapping_add(1);
+            }
+            // STXD
+            0x3 => {
+                bus.write(rx, self.d);
+                self.r[self.x] = rx.wrapping_sub(1);
+            }
+            // ADC, SDB, SMB
+            0x4 => self.add(bus.read(rx), self.df),
+            0x5 => self.subtract(bus.read(rx), self.d, self.df),
+            0x7 => self.subtract(self.d, bus.read(rx), self.df),
+            // SHRC
+            0x6 => {
+                let carry = self.d & 1 != 0;
+                self.d = self.d >> 1 | (self.df as u8) << 7;
+                self.df = carry;
+            }
+            // SAV
+            0x8 => bus.write(rx, self.t),
+            // MARK
+            0x9 => {
+                self.t = (self.x << 4 | self.p) as u8;
+                bus.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            // REQ, SEQ
+            0xa => self.q = false,
+            0xb => self.q = true,
+            // ADCI, SDBI, SMBI
+            0xc => {
+                let value = self.fetch(bus);
+                self.add(value, self.df);
+            }
+            0xd => {
+                let value = self.fetch(bus);
+                self.subtract(value, self.d, self.df);
+            }
+            0xf => {
+                let value = self.fetch(bus);
+                self.subtract(self.d, value, self.df);
+            }
+            // SHLC
+            _ => {
+                let carry = self.d & 0x80 != 0;
+                self.d = self.d << 1 | self.df as u8;
+                self.df = carry;
+            }
+        }
+    }
+
+    fn op_fn<B: Bus>(&mut self, bus: &mut B, n: usize) {
+        // the immediate forms take their operand after the opcode.
+        let value = if n >= 8 && n != 0xe {
+            self.fetch(bus)
+        } else if n != 6 && n != 0xe {
+            bus.read(self.r[self.x])
+        } else {
+            0
+        };
+        match n & 7 {
+            // LDX, LDI
+            0 => self.d = value,
+            1 => self.d |= value,
+            2 => self.d &= value,
+            3 => self.d ^= value,
+            4 => self.add(value, false),
+            // SD: memory minus D
+            5 => self.subtract(value, self.d, true),
+            // SHR, SHL
+            6 if n == 6 => {
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+            }
+            6 => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            }
+            // SM: D minus memory
+            _ => self.subtract(self.d, value, true),
+        }
+    }
+
+    fn add(&mut self, value: u8, carry: bool) {
+        let sum = self.d as u16 + value as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xff;
+    }
+
+    // DF is set when there is no borrow, and a clear DF borrows one.
+    fn subtract(&mut self, a: u8, b: u8, no_borrow: bool) {
+        let difference = a as i16 - b as i16 - !no_borrow as i16;
+        self.d = difference as u8;
+        self.df = difference >= 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Memory {
+        ram: Vec<u8>,
+        ef: [bool; 4],
+        out: Vec<(u8, u8)>,
+    }
+
+    impl Bus for Memory {
+        fn read(&mut self, address: u16) -> u8 {
+            self.ram[address as usize % self.ram.len()]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            let len = self.ram.len();
+            self.ram[address as usize % len] = value;
+        }
+
+        fn input(&mut self, port: u8) -> u8 {
+            port * 0x10
+        }
+
+        fn output(&mut self, port: u8, value: u8) {
+            self.out.push((port, value));
+        }
+
+        fn flag(&self, flag: u8) -> bool {
+            self.ef[flag as usize - 1]
+        }
+    }
+
+    fn run(program: &[u8], steps: usize) -> (Cdp1802, Memory) {
+        let mut bus = Memory {
+            ram: vec![0; 0x100],
+            ef: [false, false, true, false],
+            out: Vec::new(),
+        };
+        bus.ram[..program.len()].copy_from_slice(program);
+        let mut cpu = Cdp1802::new();
+        for _ in 0..steps {
+            cpu.step(&mut bus);
+        }
+        (cpu, bus)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        // LDI 0x80; ADI 0x90; ADCI 0; SMI 2; SHRC
+        let (cpu, _) = run(&[0xf8, 0x80, 0xfc, 0x90, 0x7c, 0x00, 0xff, 0x02], 3);
+        assert_eq!((cpu.d, cpu.df), (0x11, false));
+        let (cpu, _) = run(&[0xf8, 0x80, 0xfc, 0x90, 0x7c, 0x00, 0xff, 0x20], 4);
+        // 0x11 - 0x20 borrows
+        assert_eq!((cpu.d, cpu.df), (0xf1, false));
+        // LDI 5; SDI 3: 3 - 5
+        let (cpu, _) = run(&[0xf8, 0x05, 0xfd, 0x03], 2);
+        assert_eq!((cpu.d, cpu.df), (0xfe, false));
+        // LDI 0x81; SHLC; SHRC
+        let (cpu, _) = run(&[0xf8, 0x81, 0x7e, 0x76], 2);
+        assert_eq!((cpu.d, cpu.df), (0x02, true));
+        let (cpu, _) = run(&[0xf8, 0x81, 0x7e, 0x76], 3);
+        assert_eq!((cpu.d, cpu.df), (0x81, false));
+    }
+
+    #[test]
+    fn test_branches() {
+        // 00: B3 06; 02: LDI 1; 04: BR 08; 06: LDI 2; 08: LBNZ 0010; 0b: IDL
+        // 10: LSKP; 11: LDI 3; 13: LDI 4
+        let program = [
+            0x36, 0x06, 0xf8, 0x01, 0x30, 0x08, 0xf8, 0x02, 0xca, 0x00, 0x10, 0x00, 0, 0, 0, 0,
+            0xc8, 0xf8, 0x03, 0xf8, 0x04,
+        ];
+        let (cpu, _) = run(&program, 5);
+        assert_eq!(cpu.d, 4);
+        assert_eq!(cpu.r[0], 0x15);
+        assert_eq!(run(&program, 3).0.r[0], 0x10);
+    }
+
+    #[test]
+    fn test_call_and_io() {
+        // 00: LDI 0x10; PLO 3; SEP 3
+        // 10: SEX 2; LDI 0x80; PLO 2; INP 2; OUT 5; MARK; SEX 2; INC 2; RET
+        let mut program = vec![0; 0x20];
+        program[..4].copy_from_slice(&[0xf8, 0x10, 0xa3, 0xd3]);
+        program[0x10..0x1a]
+            .copy_from_slice(&[0xe2, 0xf8, 0x80, 0xa2, 0x6a, 0x65, 0x79, 0xe2, 0x12, 0x70]);
+        let (cpu, bus) = run(&program, 9);
+        assert_eq!(bus.out, vec![(5, 0x20)]);
+        assert_eq!(bus.ram[0x80], 0x20);
+        // MARK saved X and P, and made X the P.
+        assert_eq!(bus.ram[0x81], 0x23);
+        assert_eq!((cpu.x, cpu.p, cpu.r[2]), (3, 3, 0x80));
+
+        // RET restores them.
+        let (cpu, _) = run(&program, 12);
+        assert_eq!((cpu.x, cpu.p, cpu.r[2]), (2, 3, 0x82));
+        assert!(cpu.ie);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let (mut cpu, mut bus) = run(&[0x00], 1);
+        assert!(cpu.idle);
+        cpu.x = 2;
+        assert_eq!(cpu.interrupt(), 1);
+        assert_eq!(
+            (cpu.t, cpu.x, cpu.p, cpu.ie, cpu.idle),
+            (0x20, 2, 1, false, false)
+        );
+        assert_eq!(cpu.interrupt(), 0);
+        bus.ram[0] = 0x42;
+        cpu.r[0] = 0;
+        assert_eq!(cpu.dma_out(&mut bus), 0x42);
+        assert_eq!(cpu.r[0], 1);
+    }
+}
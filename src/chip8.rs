@@ -1,91 +1,131 @@
 use super::config::*;
+use crate::audio::{Beeper, QueuedBeeper, SoundQueue, Tone};
 use crate::cpu::Processor;
+use crate::emulator::{self, Command, Update};
+use crate::filter::Filter;
+use crate::flags::FlagStore;
+use crate::hash::fnv1a;
+use crate::movie::Movie;
+use crate::overlay;
+use crate::speed::Speed;
+use crate::wav::WavWriter;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
 use v_display::display::Display;
-use v_display::sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use v_display::sdl2::audio::{AudioDevice, AudioSpecDesired};
 use v_display::sdl2::event::Event;
 use v_display::sdl2::keyboard::Keycode;
 
-pub struct Chip8<T: Processor> {
+// how long to wait for a frame before polling the events again.
+const EVENT_POLL_TIME: Duration = Duration::from_millis(2);
+// how long the speed stays on screen after a change.
+const OSD_TIME: Duration = Duration::from_secs(2);
+
+/// The frontend: presents the frames and plays the sound of the emulation thread, and sends it
+/// the input. Rendering never holds the emulation back.
+pub struct Chip8 {
     display: Display,
-    sound_device: AudioDevice<SquareWave>,
-    cpu: T,
+    sound_device: AudioDevice<QueuedBeeper>,
+    tone: Tone,
+    emulator: emulator::Handle,
+    // the last frame received, and the buffer it is presented from with overlays.
+    frame: Vec<(u8, u8, u8)>,
+    buffer: Vec<(u8, u8, u8)>,
+    width: usize,
+    paused: bool,
+    ipf: u32,
+    frames: u64,
+    speed: Speed,
+    turbo: bool,
+    show_stats: bool,
+    osd_until: Option<Instant>,
+    ips: u64,
+    // where the user flags of the rom are saved, if they are.
+    flag_store: Option<FlagStore>,
+    rom_hash: u64,
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum State {
     Continue,
     Stop,
-}
-
-struct SquareWave {
-    phase: f32,
-    phase_inc: f32,
-    volume: f32,
-}
-
-impl AudioCallback for SquareWave {
-    type Channel = f32;
-
-    fn callback(&mut self, out: &mut [Self::Channel]) {
-        for x in out.iter_mut() {
-            *x = if self.phase >= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
-        }
-    }
+    Reset,
+    Pause,
+    Resume,
+    Step,
+    Faster,
+    Slower,
+    NormalSpeed,
+    Turbo,
+    Stats,
+    Mute,
 }
 
 macro_rules! set_key {
-    ($self:ident, $key:expr, $is_down: expr) => {
-        match $key {
-            X => $self.cpu.set_key_press(0, $is_down),
-            Num1 => $self.cpu.set_key_press(1, $is_down),
-            Num2 => $self.cpu.set_key_press(2, $is_down),
-            Num3 => $self.cpu.set_key_press(3, $is_down),
-            Q => $self.cpu.set_key_press(4, $is_down),
-            W => $self.cpu.set_key_press(5, $is_down),
-            E => $self.cpu.set_key_press(6, $is_down),
-            A => $self.cpu.set_key_press(7, $is_down),
-            S => $self.cpu.set_key_press(8, $is_down),
-            D => $self.cpu.set_key_press(9, $is_down),
-            Z => $self.cpu.set_key_press(10, $is_down),
-            C => $self.cpu.set_key_press(11, $is_down),
-            Num4 => $self.cpu.set_key_press(12, $is_down),
-            R => $self.cpu.set_key_press(13, $is_down),
-            F => $self.cpu.set_key_press(14, $is_down),
-            V => $self.cpu.set_key_press(15, $is_down),
-            _ => (),
+    ($self:ident, $key:expr, $is_down: expr) => {{
+        let key = match $key {
+            X => Some(0),
+            Num1 => Some(1),
+            Num2 => Some(2),
+            Num3 => Some(3),
+            Q => Some(4),
+            W => Some(5),
+            E => Some(6),
+            A => Some(7),
+            S => Some(8),
+            D => Some(9),
+            Z => Some(10),
+            C => Some(11),
+            Num4 => Some(12),
+            R => Some(13),
+            F => Some(14),
+            V => Some(15),
+            _ => None,
+        };
+        if let Some(key) = key {
+            $self.emulator.send(Command::Key(key, $is_down));
         }
-    };
+    }};
 }
 
-impl<T> Chip8<T>
-where
-    T: Processor,
-{
-    pub fn new(display: Display, cpu: T) -> Self {
+impl Chip8 {
+    pub fn new<T: Processor + Send + 'static>(display: Display, cpu: T) -> Self {
         let audio_subsystem = display.context.audio().unwrap();
         let desired_specs = AudioSpecDesired {
             freq: Some(44100),
             channels: Some(1),
             samples: None,
         };
+        let sound_queue = SoundQueue::new();
         let device = audio_subsystem
-            .open_playback(None, &desired_specs, |spec| SquareWave {
-                phase_inc: 440.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25,
+            .open_playback(None, &desired_specs, |spec| {
+                let beeper = Beeper::new(Tone::default(), spec.freq);
+                QueuedBeeper::new(beeper, sound_queue.clone(), spec.freq)
             })
             .unwrap();
+        // the device keeps running, it plays silence when no frames are queued.
+        device.resume();
+        let (width, height) = cpu.display_size();
         Self {
-            cpu,
             display,
             sound_device: device,
+            tone: Tone::default(),
+            emulator: emulator::Handle::spawn(cpu, sound_queue),
+            frame: vec![BG_COLOR; width * height],
+            buffer: vec![BG_COLOR; width * height],
+            width,
+            paused: false,
+            ipf: DEFAULT_IPF,
+            frames: 0,
+            speed: Speed::normal(),
+            turbo: false,
+            show_stats: false,
+            osd_until: None,
+            ips: 0,
+            flag_store: Some(FlagStore::new(FlagStore::default_dir())),
+            rom_hash: 0,
         }
     }
 
@@ -94,28 +134,230 @@ where
         let mut data = Vec::new();
         file.read_to_end(&mut data)
             .expect("The ROM could not be read.");
-        self.cpu.load_rom(&data)
-    }
-
-    pub fn run(&mut self, clock_time: u64) {
-        let mut buffer: [(u8, u8, u8); DISPLAY_WIDTH * DISPLAY_HEIGHT] =
-            [(0, 0, 0); DISPLAY_WIDTH * DISPLAY_HEIGHT];
-        while self.send_key_event() == State::Continue {
-            self.cpu.tick();
-            if self.cpu.should_redraw() {
-                self.cpu.drawn();
-                self.cpu.get_vram_buffer(&mut buffer);
-                self.display.from_buffer(&buffer);
-                self.display.refresh();
+        self.rom_hash = fnv1a(&data);
+        // the rom starts running once loaded, so its flags must be there first.
+        if let Some(store) = &self.flag_store {
+            match store.load(self.rom_hash) {
+                Ok(flags) => self.emulator.send(Command::SetFlags(flags)),
+                Err(e) => eprintln!("could not load the user flags: {}", e),
             }
-            if self.cpu.get_sound_timer() > 0 {
-                self.sound_device.resume();
-            } else {
-                self.sound_device.pause();
+        }
+        self.emulator.send(Command::Load(data));
+    }
+
+    /// Sets where the user flags are kept between sessions, `None` to start from blank flags
+    /// and not save them. Takes effect on the next `load`.
+    pub fn set_flag_store(&mut self, store: Option<FlagStore>) {
+        self.flag_store = store;
+    }
+
+    pub fn run(&mut self) {
+        loop {
+            match self.send_key_event() {
+                State::Stop => break,
+                state => self.apply(state),
+            }
+            if self.osd_until.is_some_and(|t| Instant::now() >= t) {
+                self.osd_until = None;
+                self.present();
+            }
+            match self.emulator.updates.recv_timeout(EVENT_POLL_TIME) {
+                Ok(update) => {
+                    let mut frame_updated = self.receive(update);
+                    // when presenting fell behind, only the latest frame is shown.
+                    while let Ok(update) = self.emulator.updates.try_recv() {
+                        frame_updated |= self.receive(update);
+                    }
+                    if frame_updated || self.osd_visible() {
+                        self.present();
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => {
+                    eprintln!("the emulation stopped");
+                    break;
+                }
+            }
+        }
+        // waits for the recordings to be saved.
+        self.emulator.stop();
+    }
+
+    // Takes in an update of the emulation thread, returns true if it has a new frame.
+    fn receive(&mut self, update: Update) -> bool {
+        self.frames = update.frames;
+        self.ips = update.ips;
+        if let (Some(flags), Some(store)) = (update.flags, &self.flag_store) {
+            if let Err(e) = store.save(self.rom_hash, &flags) {
+                eprintln!("could not save the user flags: {}", e);
             }
-            std::thread::sleep(std::time::Duration::from_millis(clock_time));
         }
+        match update.frame {
+            Some(frame) => {
+                self.frame = frame;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Performs the action requested by a `State`, as returned by `send_key_event`.
+    pub fn apply(&mut self, state: State) {
+        match state {
+            State::Reset => self.reset(),
+            State::Pause => self.pause(),
+            State::Resume => self.resume(),
+            State::Step => self.step_frame(),
+            State::Faster => self.set_speed(self.speed.faster()),
+            State::Slower => self.set_speed(self.speed.slower()),
+            State::NormalSpeed => self.set_speed(Speed::normal()),
+            State::Turbo => self.set_turbo(!self.turbo),
+            State::Mute => self.toggle_mute(),
+            State::Stats => {
+                self.show_stats = !self.show_stats;
+                self.present();
+            }
+            State::Continue | State::Stop => (),
+        }
+    }
+
+    /// Reloads the rom and reinitializes the processor, keeping the pause state.
+    pub fn reset(&mut self) {
+        self.emulator.send(Command::Reset);
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.emulator.send(Command::Pause);
+        self.present();
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.emulator.send(Command::Resume);
+        self.present();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Sets the number of instructions executed per 60Hz frame.
+    pub fn set_ipf(&mut self, ipf: u32) {
+        self.ipf = ipf;
+        self.emulator.send(Command::SetIpf(ipf));
+    }
+
+    pub fn ipf(&self) -> u32 {
+        self.ipf
+    }
+
+    /// The number of frames emulated since the rom was started, as of the last frame received.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone;
+        self.sound_device.lock().beeper.set_tone(tone);
+    }
+
+    /// Records the sound of every emulated frame to a WAV file, written when `run` returns.
+    pub fn record_audio(&mut self, path: &str) -> io::Result<()> {
+        let wav = WavWriter::create(path, self.tone)?;
+        self.emulator.send(Command::RecordAudio(wav));
+        Ok(())
     }
+
+    pub fn toggle_mute(&mut self) {
+        let mut player = self.sound_device.lock();
+        let muted = player.beeper.is_muted();
+        player.beeper.set_muted(!muted);
+    }
+
+    /// Records the keypad input to a movie file, written when `run` returns. The settings of the
+    /// movie must match those of the processor.
+    pub fn record_movie(&mut self, movie: Movie, path: &str) {
+        self.emulator
+            .send(Command::RecordMovie(movie, path.to_string()));
+    }
+
+    /// Replays the keypad input of a movie. The processor must be set up with its settings.
+    pub fn play_movie(&mut self, movie: Movie) {
+        self.ipf = movie.ipf;
+        self.emulator.send(Command::PlayMovie(movie));
+    }
+
+    /// Paces the emulation on the audio device's clock instead of the system timer, at normal
+    /// speed.
+    pub fn set_audio_sync(&mut self, audio_sync: bool) {
+        self.emulator.send(Command::SetAudioSync(audio_sync));
+    }
+
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.emulator.send(Command::SetFilter(filter));
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.emulator.send(Command::SetSpeed(speed));
+        self.show_osd();
+    }
+
+    /// In turbo mode the emulation is unthrottled and only a few frames per second are presented.
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+        self.emulator.send(Command::SetTurbo(turbo));
+        self.show_osd();
+    }
+
+    pub fn is_turbo(&self) -> bool {
+        self.turbo
+    }
+
+    /// The number of instructions executed per second, measured over the last second.
+    pub fn ips(&self) -> u64 {
+        self.ips
+    }
+
+    fn show_osd(&mut self) {
+        self.osd_until = Some(Instant::now() + OSD_TIME);
+        self.present();
+    }
+
+    fn osd_visible(&self) -> bool {
+        self.show_stats || self.osd_until.is_some_and(|t| Instant::now() < t)
+    }
+
+    /// Runs a single frame and presents it. Only has an effect while paused.
+    pub fn step_frame(&mut self) {
+        if self.paused {
+            self.emulator.send(Command::Step);
+        }
+    }
+
+    fn present(&mut self) {
+        self.buffer.copy_from_slice(&self.frame);
+        if self.paused {
+            overlay::draw_paused(&mut self.buffer, self.width);
+        }
+        if self.osd_visible() {
+            let speed = if self.turbo {
+                String::from("TURBO")
+            } else {
+                self.speed.to_string()
+            };
+            let ips = overlay::short_count(self.ips);
+            overlay::draw_lines(&mut self.buffer, self.width, &[&speed, &ips]);
+        }
+        self.display.from_buffer(&self.buffer);
+        self.display.refresh();
+    }
+
     pub fn send_key_event(&mut self) -> State {
         for event in self.display.get_event_pump().poll_iter() {
             use Keycode::*;
@@ -126,6 +368,42 @@ where
                 } => {
                     return State::Stop;
                 }
+                Event::KeyDown {
+                    keycode: Some(F5), ..
+                } => return State::Reset,
+                Event::KeyDown {
+                    keycode: Some(P), ..
+                } => {
+                    return if self.paused {
+                        State::Resume
+                    } else {
+                        State::Pause
+                    };
+                }
+                Event::KeyDown {
+                    keycode: Some(N), ..
+                } => return State::Step,
+                Event::KeyDown {
+                    keycode: Some(Equals),
+                    ..
+                } => return State::Faster,
+                Event::KeyDown {
+                    keycode: Some(Minus),
+                    ..
+                } => return State::Slower,
+                Event::KeyDown {
+                    keycode: Some(Num0),
+                    ..
+                } => return State::NormalSpeed,
+                Event::KeyDown {
+                    keycode: Some(Tab), ..
+                } => return State::Turbo,
+                Event::KeyDown {
+                    keycode: Some(I), ..
+                } => return State::Stats,
+                Event::KeyDown {
+                    keycode: Some(M), ..
+                } => return State::Mute,
                 Event::KeyDown {
                     keycode: Some(key), ..
                 } => set_key!(self, key, true),
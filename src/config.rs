@@ -1,5 +1,7 @@
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
+pub const DEFAULT_IPF: u32 = 10;
 pub const PIX_SIZE: usize = 20;
 pub const BG_COLOR: (u8, u8, u8) = (0, 0, 0);
 pub const FG_COLOR: (u8, u8, u8) = (0, 255, 0);
+pub const OSD_COLOR: (u8, u8, u8) = (255, 200, 0);
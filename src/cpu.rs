@@ -1,29 +1,122 @@
 use crate::config::*;
-use rand::prelude::*;
+use crate::flags::FLAG_COUNT;
+use crate::font::{Font, FONT_LEN};
+use crate::framebuffer::{Framebuffer, Rect};
+use crate::instruction::Instruction;
+use crate::megachip::{self, Blend, MegaChip};
+use crate::platform::{Colors, Platform};
+use crate::quirks::Quirks;
+use crate::rng::{Rng, RngModel};
+use crate::timing::Timing;
 
 const PGM_OFFSET: usize = 0x200;
+// addresses are 12 bits wide, they wrap around the end of the memory.
+const ADDR_MASK: usize = 0xfff;
+const STACK_SIZE: usize = 16;
 
 pub trait Processor {
     fn tick(&mut self);
     fn should_redraw(&self) -> bool;
-    fn drawn(&mut self);
-    fn get_vram_buffer(&self, buffer: &mut [(u8, u8, u8)]);
+    /// Returns the region of the screen changed since the last call, if any.
+    fn take_dirty(&mut self) -> Option<Rect>;
+    /// Writes the pixels of `rect` to the buffer, which covers the whole screen.
+    fn get_vram_region(&self, buffer: &mut [(u8, u8, u8)], rect: Rect);
     fn load_rom(&mut self, rom: &[u8]);
     fn set_key_press(&mut self, key: u8, is_down: bool);
     fn get_sound_timer(&self) -> u8;
+    fn reset(&mut self);
+    fn tick_timers(&mut self);
+
+    /// The width and height of the screen, in pixels.
+    fn display_size(&self) -> (usize, usize) {
+        (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+    }
+
+    /// The RPL user flags, which outlive the runs of a rom.
+    fn user_flags(&self) -> [u8; FLAG_COUNT] {
+        [0; FLAG_COUNT]
+    }
+
+    fn set_user_flags(&mut self, _flags: [u8; FLAG_COUNT]) {}
+
+    /// The digitised sound played during the last frame, spread evenly over it, if any.
+    fn take_samples(&mut self) -> Option<Vec<f32>> {
+        None
+    }
+
+    fn get_vram_buffer(&self, buffer: &mut [(u8, u8, u8)]) {
+        let (width, height) = self.display_size();
+        self.get_vram_region(buffer, Rect::full(width, height));
+    }
+
+    /// Signals the vertical blank at the end of a frame.
+    fn vblank(&mut self) {}
+
+    /// Whether the processor is stalled until the next vertical blank.
+    fn waiting_vblank(&self) -> bool {
+        false
+    }
+
+    /// Runs one 60Hz frame: up to `ipf` instructions followed by a timer update and the
+    /// vertical blank.
+    fn run_frame(&mut self, ipf: u32) -> Frame {
+        let mut ticks = 0;
+        while ticks < ipf && !self.waiting_vblank() {
+            self.tick();
+            ticks += 1;
+        }
+        let beep = self.get_sound_timer() > 0;
+        self.tick_timers();
+        self.vblank();
+        Frame { ticks, beep }
+    }
+}
+
+/// What happened during a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    /// The number of instructions that were executed.
+    pub ticks: u32,
+    /// Whether the beeper sounds for the duration of the frame.
+    pub beep: bool,
 }
 
 pub struct CPU {
     pub v: [u8; 16],
     pub i: usize,
-    pub ram: [u8; 4096],
-    pub vram: [bool; 64 * 32],
+    /// 4K, or 16MB on MegaChip.
+    pub ram: Vec<u8>,
+    pub vram: Framebuffer,
     pub stack: Vec<usize>,
     pub pc: usize,
     pub delay: u8,
     pub sound: u8,
     pub key_press: [bool; 16],
-    pub draw: bool,
+    pub quirks: Quirks,
+    pub rng: Rng,
+    pub vblank: VBlank,
+    pub timing: Timing,
+    /// Set with `set_platform`, which resizes the screen.
+    pub platform: Platform,
+    /// The colour board of CHIP-8X.
+    pub colors: Colors,
+    /// The RPL user flags of SCHIP, kept by resets.
+    pub flags: [u8; FLAG_COUNT],
+    /// The colour screen and the sound of MegaChip, on that platform only.
+    pub mega: Option<MegaChip>,
+    /// Set with `set_font`, which copies it to memory.
+    pub font: Font,
+    // the time left in the current frame, negative when the last instruction ran over.
+    cycles: i64,
+    /// Instructions already decoded, by address, when the decode cache is enabled.
+    decoded: Option<Box<[Option<Instruction>; 4096]>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VBlank {
+    Idle,
+    Waiting,
+    Reached,
 }
 
 enum PcJump {
@@ -44,129 +137,362 @@ impl PcJump {
 
 impl CPU {
     pub fn new() -> Self {
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self {
         let mut cpu = Self {
             v: [0; 16],
             i: 0,
-            stack: Vec::with_capacity(16),
-            ram: [0; 4096],
-            vram: [false; 64 * 32],
+            stack: Vec::with_capacity(STACK_SIZE),
+            ram: vec![0; Platform::default().memory_size()],
+            vram: Framebuffer::new(),
             pc: PGM_OFFSET,
             delay: 0,
             sound: 0,
             key_press: [false; 16],
-            draw: false,
+            quirks,
+            rng: Rng::new(RngModel::Xorshift, 0),
+            vblank: VBlank::Idle,
+            timing: Timing::default(),
+            platform: Platform::default(),
+            colors: Colors::new(DISPLAY_HEIGHT),
+            flags: [0; FLAG_COUNT],
+            mega: None,
+            font: Font::default(),
+            cycles: 0,
+            decoded: None,
         };
-        cpu.mem_cpy(&include!("chars.in"), 0);
+        let glyphs = cpu.font.glyphs;
+        cpu.mem_cpy(&glyphs, cpu.font.address);
         cpu
     }
+
+    /// Replaces the glyphs in memory with those of `font`, at its address, which must leave room
+    /// for them. What a program wrote over the old glyphs is kept.
+    pub fn set_font(&mut self, font: Font) -> Result<(), String> {
+        if font.address + FONT_LEN > self.ram.len() {
+            return Err(format!(
+                "a font at 0x{:x} doesn't fit in the memory",
+                font.address
+            ));
+        }
+        let old = self.font.address;
+        for (byte, &glyph) in self.ram[old..old + FONT_LEN]
+            .iter_mut()
+            .zip(self.font.glyphs.iter())
+        {
+            if *byte == glyph {
+                *byte = 0;
+            }
+        }
+        self.mem_cpy(&font.glyphs, font.address);
+        self.font = font;
+        Ok(())
+    }
+
+    /// Switches to another variant of CHIP-8, with a blank screen of its size and the pc at the
+    /// start of its programs.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        self.vram = Framebuffer::with_height(platform.display_height());
+        self.colors = Colors::new(platform.display_height());
+        self.ram.resize(platform.memory_size(), 0);
+        self.mega = if platform == Platform::MegaChip {
+            Some(MegaChip::new())
+        } else {
+            None
+        };
+        self.pc = platform.pgm_offset();
+        self.clear_decode_cache();
+    }
+
+    /// Enables caching the decoded instructions by address, so that loops aren't decoded again.
+    /// Decoding is cheap though, compare the engines with `cargo bench` before relying on it.
+    /// Writes to the memory through `poke` or the instructions invalidate the cache, direct
+    /// writes to `ram` must be followed by `clear_decode_cache`.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decoded = if enabled {
+            Some(Box::new([None; 4096]))
+        } else {
+            None
+        };
+    }
+
+    pub fn has_decode_cache(&self) -> bool {
+        self.decoded.is_some()
+    }
+
+    pub fn clear_decode_cache(&mut self) {
+        if let Some(decoded) = &mut self.decoded {
+            decoded.iter_mut().for_each(|inst| *inst = None);
+        }
+    }
+
+    /// Writes a byte to memory, invalidating the instructions that contain it.
+    pub fn poke(&mut self, address: usize, value: u8) {
+        let address = address & (self.ram.len() - 1);
+        self.ram[address] = value;
+        if let Some(decoded) = self.decoded.as_mut().filter(|_| address <= ADDR_MASK) {
+            decoded[address] = None;
+            decoded[address.wrapping_sub(1) & ADDR_MASK] = None;
+        }
+    }
+
+    /// Decodes the instruction at `address`.
+    pub fn decode_at(&self, address: usize) -> Instruction {
+        let hi = self.ram[address & ADDR_MASK] as u16;
+        let lo = self.ram[(address + 1) & ADDR_MASK] as u16;
+        let inst = hi << 8 | lo;
+        // hi-res roms start by jumping to the hi-res interpreter they carry, which is replaced by
+        // the native instructions, to the program after it.
+        if self.platform == Platform::HiRes && address == PGM_OFFSET && inst == 0x1260 {
+            return Instruction::Jp(0x2c0);
+        }
+        Instruction::decode_for(inst, self.platform)
+    }
+
+    /// Starts a 60Hz frame, with the time `timing` allows for it. The time an instruction ran
+    /// over the last frame is taken from this one.
+    pub fn start_frame(&mut self, ipf: u32) {
+        let debt = match self.timing {
+            Timing::Ipf => 0,
+            Timing::Vip => self.cycles.min(0),
+        };
+        self.cycles = debt + self.timing.frame_budget(ipf) as i64;
+    }
+
+    /// Whether there is time left in the frame.
+    pub fn has_cycles(&self) -> bool {
+        self.cycles > 0
+    }
+
+    /// Executes a decoded instruction as if it was at the pc, and moves the pc.
+    pub fn step(&mut self, inst: Instruction) {
+        self.cycles -= self.timing.cost(self, inst) as i64;
+        let jump = self.execute(inst).to_int();
+        self.pc = (self.pc + jump) & ADDR_MASK;
+    }
 }
 
 impl Processor for CPU {
+    fn reset(&mut self) {
+        let mut rng = self.rng.clone();
+        rng.restart();
+        let decode_cache = self.has_decode_cache();
+        let timing = self.timing;
+        let platform = self.platform;
+        let flags = self.flags;
+        let font = self.font.clone();
+        *self = Self::with_quirks(self.quirks);
+        self.flags = flags;
+        self.rng = rng;
+        self.timing = timing;
+        self.set_platform(platform);
+        // it fitted in the memory of the platform already.
+        self.set_font(font).unwrap();
+        self.set_decode_cache(decode_cache);
+    }
+
     fn get_sound_timer(&self) -> u8 {
         self.sound
     }
 
+    fn user_flags(&self) -> [u8; FLAG_COUNT] {
+        self.flags
+    }
+
+    fn set_user_flags(&mut self, flags: [u8; FLAG_COUNT]) {
+        self.flags = flags;
+    }
+
+    fn tick_timers(&mut self) {
+        self.rng.tick_frame();
+        if self.delay > 0 {
+            self.delay -= 1;
+        }
+        if self.sound > 0 {
+            self.sound -= 1;
+        }
+    }
+
+    fn vblank(&mut self) {
+        if self.vblank == VBlank::Waiting {
+            self.vblank = VBlank::Reached;
+        }
+    }
+
+    fn waiting_vblank(&self) -> bool {
+        self.vblank == VBlank::Waiting
+    }
+
     fn set_key_press(&mut self, key: u8, is_down: bool) {
-        self.key_press[key as usize] = is_down;
+        self.key_press[key as usize & 0xf] = is_down;
     }
 
     fn load_rom(&mut self, rom: &[u8]) {
-        self.mem_cpy(&rom, PGM_OFFSET);
+        // whatever doesn't fit in memory is dropped.
+        let offset = self.platform.pgm_offset();
+        let len = rom.len().min(self.ram.len() - offset);
+        self.mem_cpy(&rom[..len], offset);
     }
 
-    fn get_vram_buffer(&self, buffer: &mut [(u8, u8, u8)]) {
-        for i in 0..self.vram.len() {
-            if self.vram[i] {
-                buffer[i] = (0xff, 0xff, 0xff);
-            } else {
-                buffer[i] = (0, 0, 0);
+    fn display_size(&self) -> (usize, usize) {
+        match self.mega {
+            Some(_) => (megachip::WIDTH, megachip::HEIGHT),
+            None => (DISPLAY_WIDTH, self.vram.height()),
+        }
+    }
+
+    fn get_vram_region(&self, buffer: &mut [(u8, u8, u8)], rect: Rect) {
+        if let Some(mega) = &self.mega {
+            mega.write_region(&self.vram, buffer, rect);
+        } else if self.platform == Platform::Chip8X {
+            for y in rect.y..rect.y + rect.height {
+                for x in rect.x..rect.x + rect.width {
+                    buffer[y * DISPLAY_WIDTH + x] = self.colors.pixel(x, y, self.vram.get(x, y));
+                }
             }
+        } else {
+            self.vram.write_region(buffer, rect);
         }
     }
 
     fn should_redraw(&self) -> bool {
-        self.draw
+        self.vram.is_dirty() || self.mega.as_ref().is_some_and(MegaChip::is_dirty)
     }
-    fn drawn(&mut self) {
-        self.draw = false;
+
+    fn take_dirty(&mut self) -> Option<Rect> {
+        let dirty = self.vram.take_dirty();
+        match self.mega.as_mut() {
+            // the monochrome screen is scaled up, the whole picture is redrawn.
+            Some(mega) => (mega.take_dirty() || dirty.is_some())
+                .then(|| Rect::full(megachip::WIDTH, megachip::HEIGHT)),
+            None => dirty,
+        }
+    }
+
+    fn take_samples(&mut self) -> Option<Vec<f32>> {
+        self.mega.as_mut().and_then(MegaChip::take_samples)
     }
 
     fn tick(&mut self) {
-        let hi = self.ram[self.pc] as u16;
-        let lo = self.ram[self.pc + 1] as u16;
-        let inst = hi << 8 | lo;
+        let inst = match self
+            .decoded
+            .as_ref()
+            .and_then(|decoded| decoded[self.pc & ADDR_MASK])
+        {
+            Some(inst) => inst,
+            None => {
+                let inst = self.decode_at(self.pc);
+                if let Some(decoded) = &mut self.decoded {
+                    decoded[self.pc & ADDR_MASK] = Some(inst);
+                }
+                inst
+            }
+        };
+        self.step(inst);
+    }
 
-        let nibs = (
-            (inst & 0xf000) >> 12,
-            (inst & 0x0f00) >> 8,
-            (inst & 0x00f0) >> 4,
-            (inst & 0x000f),
-        );
-
-        let nnn = (inst & 0x0fff) as usize;
-        let nn = (inst & 0x00ff) as u8;
-        let n = nibs.3 as u8;
-        let x = nibs.1 as usize;
-        let y = nibs.2 as usize;
-
-        self.pc += match nibs {
-            (0x00, 0x00, 0x0e, 0x0e) => self.op_00ee(),
-            (0x00, 0x00, 0x0e, 0x00) => self.op_00e0(),
-            (0x01, _, _, _) => self.op_1nnn(nnn),
-            (0x02, _, _, _) => self.op_2nnn(nnn),
-            (0x03, _, _, _) => self.op_3xnn(x, nn),
-            (0x04, _, _, _) => self.op_4xnn(x, nn),
-            (0x05, _, _, 0x00) => self.op_5xy0(x, y),
-            (0x06, _, _, _) => self.op_6xnn(x, nn),
-            (0x07, _, _, _) => self.op_7xnn(x, nn),
-            (0x08, _, _, 0x00) => self.op_8xy0(x, y),
-            (0x08, _, _, 0x01) => self.op_8xy1(x, y),
-            (0x08, _, _, 0x02) => self.op_8xy2(x, y),
-            (0x08, _, _, 0x03) => self.op_8xy3(x, y),
-            (0x08, _, _, 0x04) => self.op_8xy4(x, y),
-            (0x08, _, _, 0x05) => self.op_8xy5(x, y),
-            (0x08, _, _, 0x06) => self.op_8xy6(x, y),
-            (0x08, _, _, 0x07) => self.op_8xy7(x, y),
-            (0x08, _, _, 0x0e) => self.op_8xye(x, y),
-            (0x09, _, _, 0x00) => self.op_9xy0(x, y),
-            (0x0a, _, _, _) => self.op_annn(nnn),
-            (0x0b, _, _, _) => self.op_bnnn(nnn),
-            (0x0c, _, _, _) => self.op_cxnn(x, nn),
-            (0x0d, _, _, _) => self.op_dxyn(x, y, n),
-            (0x0e, _, 0x09, 0x0e) => self.op_ex9e(x),
-            (0x0e, _, 0x0a, 0x01) => self.op_exa1(x),
-            (0x0f, _, 0x00, 0x07) => self.op_fx07(x),
-            (0x0f, _, 0x00, 0x0a) => self.op_fx0a(x),
-            (0x0f, _, 0x01, 0x05) => self.op_fx15(x),
-            (0x0f, _, 0x01, 0x08) => self.op_fx18(x),
-            (0x0f, _, 0x01, 0x0e) => self.op_fx1e(x),
-            (0x0f, _, 0x02, 0x09) => self.op_fx29(x),
-            (0x0f, _, 0x03, 0x03) => self.op_fx33(x),
-            (0x0f, _, 0x05, 0x05) => self.op_fx55(x),
-            (0x0f, _, 0x06, 0x05) => self.op_fx65(x),
-            _ => PcJump::Next,
-        }
-        .to_int();
-        //reinit keypress
-        if self.delay > 0 {
-            self.delay -= 1;
-        }
-        if self.sound > 0 {
-            self.sound -= 1;
+    fn run_frame(&mut self, ipf: u32) -> Frame {
+        self.start_frame(ipf);
+        let mut ticks = 0;
+        while self.has_cycles() && !self.waiting_vblank() {
+            self.tick();
+            ticks += 1;
         }
+        let beep = self.get_sound_timer() > 0;
+        self.tick_timers();
+        self.vblank();
+        Frame { ticks, beep }
     }
 }
 
 impl CPU {
-    // RET
+    fn execute(&mut self, inst: Instruction) -> PcJump {
+        match inst {
+            Instruction::Ret => self.op_00ee(),
+            Instruction::Cls => self.op_00e0(),
+            Instruction::Jp(nnn) => self.op_1nnn(nnn),
+            Instruction::Call(nnn) => self.op_2nnn(nnn),
+            Instruction::SeImm(x, nn) => self.op_3xnn(x, nn),
+            Instruction::SneImm(x, nn) => self.op_4xnn(x, nn),
+            Instruction::Se(x, y) => self.op_5xy0(x, y),
+            Instruction::LdImm(x, nn) => self.op_6xnn(x, nn),
+            Instruction::AddImm(x, nn) => self.op_7xnn(x, nn),
+            Instruction::Ld(x, y) => self.op_8xy0(x, y),
+            Instruction::Or(x, y) => self.op_8xy1(x, y),
+            Instruction::And(x, y) => self.op_8xy2(x, y),
+            Instruction::Xor(x, y) => self.op_8xy3(x, y),
+            Instruction::Add(x, y) => self.op_8xy4(x, y),
+            Instruction::Sub(x, y) => self.op_8xy5(x, y),
+            Instruction::Shr(x, y) => self.op_8xy6(x, y),
+            Instruction::Subn(x, y) => self.op_8xy7(x, y),
+            Instruction::Shl(x, y) => self.op_8xye(x, y),
+            Instruction::Sne(x, y) => self.op_9xy0(x, y),
+            Instruction::LdI(nnn) => self.op_annn(nnn),
+            Instruction::JpV0(nnn) => self.op_bnnn(nnn),
+            Instruction::Rnd(x, nn) => self.op_cxnn(x, nn),
+            Instruction::Drw(x, y, n) => self.op_dxyn(x, y, n),
+            Instruction::Skp(x) => self.op_ex9e(x),
+            Instruction::Sknp(x) => self.op_exa1(x),
+            Instruction::LdVxDt(x) => self.op_fx07(x),
+            Instruction::LdVxK(x) => self.op_fx0a(x),
+            Instruction::LdDtVx(x) => self.op_fx15(x),
+            Instruction::LdStVx(x) => self.op_fx18(x),
+            Instruction::AddI(x) => self.op_fx1e(x),
+            Instruction::LdF(x) => self.op_fx29(x),
+            Instruction::LdHf(x) => self.op_fx30(x),
+            Instruction::LdB(x) => self.op_fx33(x),
+            Instruction::LdMemVx(x) => self.op_fx55(x),
+            Instruction::LdVxMem(x) => self.op_fx65(x),
+            Instruction::LdFlagsVx(x) => self.op_fx75(x),
+            Instruction::LdVxFlags(x) => self.op_fx85(x),
+            Instruction::BgColor => self.op_02a0(),
+            Instruction::AddColors(x, y) => self.op_5xy1(x, y),
+            Instruction::Color(x, y, n) => self.op_bxyn(x, y, n),
+            // nothing is plugged on the second keypad.
+            Instruction::SkpPad2(_) => PcJump::Next,
+            Instruction::SknpPad2(_) => PcJump::Skip,
+            // the VP-595 tone generator isn't emulated, nor anything on the input port.
+            Instruction::Out(_) => PcJump::Next,
+            Instruction::In(x) => self.op_6xnn(x, 0),
+            Instruction::MegaOff => self.with_mega(|mega| mega.set_enabled(false)),
+            Instruction::MegaOn => self.with_mega(|mega| mega.set_enabled(true)),
+            Instruction::LdIFar(nn) => self.op_01nn(nn),
+            Instruction::LdPalette(nn) => self.op_02nn(nn),
+            Instruction::SpriteWidth(nn) => {
+                self.with_mega(|mega| mega.sprite_width = if nn == 0 { 256 } else { nn as usize })
+            }
+            Instruction::SpriteHeight(nn) => {
+                self.with_mega(|mega| mega.sprite_height = if nn == 0 { 256 } else { nn as usize })
+            }
+            Instruction::ScreenAlpha(nn) => self.with_mega(|mega| mega.alpha = nn),
+            Instruction::PlaySample(looping) => self.op_060n(looping),
+            Instruction::StopSample => self.with_mega(MegaChip::stop),
+            Instruction::BlendMode(n) => self.with_mega(|mega| mega.blend = Blend::from_mode(n)),
+            Instruction::CollisionColor(nn) => self.with_mega(|mega| mega.collision = nn),
+            Instruction::Unknown => PcJump::Next,
+        }
+    }
+
+    // RET, ignored if the stack is empty
     fn op_00ee(&mut self) -> PcJump {
-        self.pc = self.stack.pop().expect("Stack Underflow!") as usize;
-        PcJump::None
+        match self.stack.pop() {
+            Some(pc) => {
+                self.pc = pc;
+                PcJump::None
+            }
+            None => PcJump::Next,
+        }
     }
 
-    // CLS: Clear screen
+    // CLS: Clear screen, or show the MegaChip screen drawn since the last one
     fn op_00e0(&mut self) -> PcJump {
-        self.vram.iter_mut().for_each(|x| *x = false);
+        match self.mega.as_mut().filter(|mega| mega.enabled) {
+            Some(mega) => mega.clear(),
+            None => self.vram.clear(),
+        }
         PcJump::Next
     }
 
@@ -176,9 +502,12 @@ impl CPU {
         PcJump::None
     }
 
-    // CALL nnn
+    // CALL nnn, ignored if the stack is full
     fn op_2nnn(&mut self, nnn: usize) -> PcJump {
-        self.stack.push(self.pc + 2);
+        if self.stack.len() == STACK_SIZE {
+            return PcJump::Next;
+        }
+        self.stack.push((self.pc + 2) & ADDR_MASK);
         self.pc = nnn as usize;
         PcJump::None
     }
@@ -230,65 +559,64 @@ impl CPU {
         PcJump::Next
     }
 
-    // TODO: write test
+    // OR Vy in Vx
     fn op_8xy1(&mut self, x: usize, y: usize) -> PcJump {
         self.v[x] |= self.v[y];
         PcJump::Next
     }
 
-    // TODO: write test
+    // AND Vy in Vx
     fn op_8xy2(&mut self, x: usize, y: usize) -> PcJump {
         self.v[x] &= self.v[y];
         PcJump::Next
     }
 
-    // TODO: write test
+    // XOR Vy in Vx
     fn op_8xy3(&mut self, x: usize, y: usize) -> PcJump {
         self.v[x] ^= self.v[y];
         PcJump::Next
     }
 
+    // ADD Vy to Vx, VF is set on carry
     fn op_8xy4(&mut self, x: usize, y: usize) -> PcJump {
-        self.v[0xf] = 0;
-        let vx = self.v[x] as u16;
-        let vy = self.v[y] as u16;
-        let vx = vx + vy;
-        if vx > 0xff {
-            self.v[0xf] = 1;
-        }
-        self.v[x] = vx as u8;
+        let (vx, carry) = self.v[x].overflowing_add(self.v[y]);
+        self.set_with_flag(x, vx, carry as u8);
         PcJump::Next
     }
 
-    // TODO: write test
+    // SUB Vy from Vx, VF is set when there is no borrow
     fn op_8xy5(&mut self, x: usize, y: usize) -> PcJump {
-        self.v[0xf] = if self.v[x] > self.v[y] { 1 } else { 0 };
-        self.v[x] = self.v[x].wrapping_sub(self.v[y]);
+        let (vx, borrow) = self.v[x].overflowing_sub(self.v[y]);
+        self.set_with_flag(x, vx, !borrow as u8);
         PcJump::Next
     }
 
-    // TODO: write test
+    // SHR Vx, VF is set to the bit shifted out
     fn op_8xy6(&mut self, x: usize, _y: usize) -> PcJump {
-        self.v[0xf] = self.v[x] & 0x01;
-        self.v[x] >>= 1;
+        self.set_with_flag(x, self.v[x] >> 1, self.v[x] & 0x01);
         PcJump::Next
     }
 
-    // TODO: write test
+    // SUBN: Vx = Vy - Vx, VF is set when there is no borrow
     fn op_8xy7(&mut self, x: usize, y: usize) -> PcJump {
-        self.v[0xf] = if self.v[y] > self.v[x] { 1 } else { 0 };
-        self.v[x] = self.v[y].wrapping_sub(self.v[x]);
+        let (vx, borrow) = self.v[y].overflowing_sub(self.v[x]);
+        self.set_with_flag(x, vx, !borrow as u8);
         PcJump::Next
     }
 
-    // TODO: write test
+    // SHL Vx, VF is set to the bit shifted out
     fn op_8xye(&mut self, x: usize, _y: usize) -> PcJump {
-        self.v[0xf] = (self.v[x] & 0x80) >> 7;
-        self.v[x] <<= 1;
+        self.set_with_flag(x, self.v[x] << 1, (self.v[x] & 0x80) >> 7);
         PcJump::Next
     }
 
-    // TODO: write test
+    /// Sets Vx and then the flag, so that the flag wins when x is F.
+    fn set_with_flag(&mut self, x: usize, value: u8, flag: u8) {
+        self.v[x] = value;
+        self.v[0xf] = flag;
+    }
+
+    //SKIP.NE: skip if Vx != Vy
     fn op_9xy0(&mut self, x: usize, y: usize) -> PcJump {
         if self.v[x] != self.v[y] {
             PcJump::Skip
@@ -297,67 +625,75 @@ impl CPU {
         }
     }
 
-    // TODO: write test
+    // LOAD nnn in I
     fn op_annn(&mut self, nnn: usize) -> PcJump {
         self.i = nnn;
         PcJump::Next
     }
 
-    // TODO: write test
+    // JMP to nnn + V0
     fn op_bnnn(&mut self, nnn: usize) -> PcJump {
         self.pc = self.v[0] as usize + nnn;
         PcJump::None
     }
 
-    // TODO: write test
+    // RND: random byte AND nn in Vx
     fn op_cxnn(&mut self, x: usize, nn: u8) -> PcJump {
-        self.v[x] = random::<u8>() & nn;
+        self.v[x] = self.rng.next_byte() & nn;
         PcJump::Next
     }
 
     fn op_dxyn(&mut self, x: usize, y: usize, n: u8) -> PcJump {
-        self.v[0xf] = 0;
-        for j in 0..n {
-            let y = (self.v[y] as usize + j as usize) % DISPLAY_HEIGHT;
-            for i in 0..8 {
-                let x = (self.v[x] as usize + i) % DISPLAY_WIDTH;
-                let old_pix_color = self.vram[y * DISPLAY_WIDTH + x];
-                let new_pix_color = self.ram[self.i as usize + j as usize] & (0x80 >> i) != 0;
-                if old_pix_color && new_pix_color {
-                    self.v[0xf] = 1;
-                }
-                self.vram[y * DISPLAY_WIDTH + x] = new_pix_color ^ old_pix_color;
+        if self.quirks.display_wait {
+            // stall on this instruction until the frame boundary.
+            if self.vblank != VBlank::Reached {
+                self.vblank = VBlank::Waiting;
+                return PcJump::None;
+            }
+            self.vblank = VBlank::Idle;
+        }
+        if self.mega.as_ref().is_some_and(|mega| mega.enabled) {
+            return self.draw_mega(x, y);
+        }
+        let vx = self.v[x] as usize % DISPLAY_WIDTH;
+        let vy = self.v[y] as usize;
+        let height = self.vram.height();
+        let mut collision = 0;
+        for j in 0..n as usize {
+            let row = self.ram[self.addr(j)];
+            if self.vram.xor_sprite_row(vx, (vy + j) % height, row) {
+                collision = 1;
             }
         }
-        self.draw = true;
+        self.v[0xf] = collision;
         PcJump::Next
     }
 
-    // TODO: write test
+    //SKIP.KEY: skip if the key Vx is pressed
     fn op_ex9e(&mut self, x: usize) -> PcJump {
-        if self.key_press[self.v[x] as usize] {
+        if self.key_press[self.v[x] as usize & 0xf] {
             PcJump::Skip
         } else {
             PcJump::Next
         }
     }
 
-    // TODO: write test
+    //SKIP.NKEY: skip if the key Vx is not pressed
     fn op_exa1(&mut self, x: usize) -> PcJump {
-        if !self.key_press[self.v[x] as usize] {
+        if !self.key_press[self.v[x] as usize & 0xf] {
             PcJump::Skip
         } else {
             PcJump::Next
         }
     }
 
-    // TODO: write test
+    // LOAD the delay timer in Vx
     fn op_fx07(&mut self, x: usize) -> PcJump {
         self.v[x] = self.delay;
         PcJump::Next
     }
 
-    // TODO: write test
+    // WAIT for a key press, and LOAD the key in Vx
     fn op_fx0a(&mut self, x: usize) -> PcJump {
         if self.key_press.iter().any(|k| *k) {
             self.v[x] = self
@@ -373,59 +709,162 @@ impl CPU {
         }
     }
 
-    // TODO: write test
+    // LOAD Vx in the delay timer
     fn op_fx15(&mut self, x: usize) -> PcJump {
         self.delay = self.v[x] as u8;
         PcJump::Next
     }
 
-    // TODO: write test
+    // LOAD Vx in the sound timer
     fn op_fx18(&mut self, x: usize) -> PcJump {
         self.sound = self.v[x] as u8;
         PcJump::Next
     }
 
-    // TODO: write test
+    // ADD Vx to I
     fn op_fx1e(&mut self, x: usize) -> PcJump {
         self.i += self.v[x] as usize;
         self.v[0x0f] = if self.i > 0x0F00 { 1 } else { 0 };
         PcJump::Next
     }
 
-    // TODO: write test
+    // LOAD the address of the glyph of Vx in I
     fn op_fx29(&mut self, x: usize) -> PcJump {
-        self.i = self.v[x] as usize * 5;
+        self.i = self.font.small_glyph(self.v[x]);
+        PcJump::Next
+    }
+
+    // LOAD the address of the big glyph of Vx in I
+    fn op_fx30(&mut self, x: usize) -> PcJump {
+        self.i = self.font.big_glyph(self.v[x]);
         PcJump::Next
     }
 
     fn op_fx33(&mut self, x: usize) -> PcJump {
-        let i = self.i;
-        self.ram[i] = self.v[x] / 100;
-        self.ram[i + 1] = (self.v[x] % 100) / 10;
-        self.ram[i + 2] = self.v[x] % 10;
+        let vx = self.v[x];
+        self.poke(self.i, vx / 100);
+        self.poke(self.i + 1, (vx % 100) / 10);
+        self.poke(self.i + 2, vx % 10);
         PcJump::Next
     }
 
-    // TODO: write test
+    // STORE V0 to Vx at I
     fn op_fx55(&mut self, x: usize) -> PcJump {
         for n in 0..=x {
-            self.ram[self.i as usize + n] = self.v[n] as u8;
+            self.poke(self.i + n, self.v[n]);
         }
         PcJump::Next
     }
 
-    // TODO: write test
+    // LOAD V0 to Vx from I
     fn op_fx65(&mut self, x: usize) -> PcJump {
         for i in 0..=x {
-            self.v[i] = self.ram[self.i + i];
+            self.v[i] = self.ram[self.addr(i)];
+        }
+        PcJump::Next
+    }
+
+    // STORE V0 to Vx in the user flags
+    fn op_fx75(&mut self, x: usize) -> PcJump {
+        self.flags[..=x].copy_from_slice(&self.v[..=x]);
+        PcJump::Next
+    }
+
+    // LOAD V0 to Vx from the user flags
+    fn op_fx85(&mut self, x: usize) -> PcJump {
+        self.v[..=x].copy_from_slice(&self.flags[..=x]);
+        PcJump::Next
+    }
+
+    // BACKGROUND: cycle the background colour
+    fn op_02a0(&mut self) -> PcJump {
+        self.colors.next_background();
+        self.vram
+            .mark_dirty(Rect::full(DISPLAY_WIDTH, self.vram.height()));
+        PcJump::Next
+    }
+
+    // ADD the colours in Vy to Vx, without carry from a colour to the next
+    fn op_5xy1(&mut self, x: usize, y: usize) -> PcJump {
+        self.v[x] = ((self.v[x] & 0x77) + (self.v[y] & 0x77)) & 0x77;
+        PcJump::Next
+    }
+
+    // COLOR: with n = 0, the low nibbles of Vx and Vx+1 are the first zone column and row of
+    // 4 lines, the high ones the number of more zones to colour. Otherwise the zone at the
+    // pixel Vx, Vx+1 is coloured on n lines.
+    fn op_bxyn(&mut self, x: usize, y: usize, n: u8) -> PcJump {
+        let (h, v) = (self.v[x] as usize, self.v[(x + 1) & 0xf] as usize);
+        let color = self.v[y];
+        if n == 0 {
+            let (column, columns) = (h & 0xf, (h >> 4) + 1);
+            let (row, rows) = ((v & 0xf) * 4, ((v >> 4) + 1) * 4);
+            self.colors.fill(column, row, columns, rows, color);
+        } else {
+            let row = v % self.vram.height();
+            self.colors
+                .fill(h % DISPLAY_WIDTH / 8, row, 1, n as usize, color);
+        }
+        self.vram
+            .mark_dirty(Rect::full(DISPLAY_WIDTH, self.vram.height()));
+        PcJump::Next
+    }
+
+    // Runs a MegaChip instruction, which does nothing on the other platforms.
+    fn with_mega(&mut self, op: impl FnOnce(&mut MegaChip)) -> PcJump {
+        if let Some(mega) = self.mega.as_mut() {
+            op(mega);
         }
         PcJump::Next
     }
 
-    // TODO: write test
+    // LOAD the 24 bit address nn and the next word in I
+    fn op_01nn(&mut self, nn: u8) -> PcJump {
+        let hi = self.ram[(self.pc + 2) & ADDR_MASK] as usize;
+        let lo = self.ram[(self.pc + 3) & ADDR_MASK] as usize;
+        self.i = (nn as usize) << 16 | hi << 8 | lo;
+        PcJump::Skip
+    }
+
+    // PALETTE: load nn colours from I
+    fn op_02nn(&mut self, nn: u8) -> PcJump {
+        let colors: Vec<u8> = (0..nn as usize * 4)
+            .map(|k| self.ram[self.addr(k)])
+            .collect();
+        self.with_mega(|mega| mega.load_palette(&colors))
+    }
+
+    // SOUND: play the digitised sound at I
+    fn op_060n(&mut self, looping: bool) -> PcJump {
+        let byte = |k: usize| self.ram[self.addr(k)];
+        let rate = (byte(0) as u32) << 8 | byte(1) as u32;
+        let len = (byte(2) as usize) << 16 | (byte(3) as usize) << 8 | byte(4) as usize;
+        let data = (0..len)
+            .map(|k| byte(megachip::SAMPLE_HEADER_LEN + k))
+            .collect();
+        self.with_mega(|mega| mega.play(rate, data, looping))
+    }
+
+    // DRW a sprite of colour indices at Vx, Vy, on the MegaChip screen
+    fn draw_mega(&mut self, x: usize, y: usize) -> PcJump {
+        let (vx, vy) = (self.v[x] as usize, self.v[y] as usize);
+        let mega = self.mega.as_ref().unwrap();
+        let len = mega.sprite_width * mega.sprite_height;
+        let sprite: Vec<u8> = (0..len).map(|k| self.ram[self.addr(k)]).collect();
+        let collision = self.mega.as_mut().unwrap().draw(vx, vy, &sprite);
+        self.v[0xf] = collision as u8;
+        PcJump::Next
+    }
+
+    /// The address `offset` bytes after I.
+    fn addr(&self, offset: usize) -> usize {
+        (self.i + offset) & (self.ram.len() - 1)
+    }
+
     fn mem_cpy(&mut self, src: &[u8], offset: usize) {
         let slice = &mut self.ram[offset..(offset + src.len())];
         slice.clone_from_slice(src);
+        self.clear_decode_cache();
     }
 }
 
@@ -433,12 +872,464 @@ impl CPU {
 mod tests {
 
     use super::*;
+    use crate::font::FontSet;
 
     #[test]
     fn test_init_cpu() {
         let cpu = CPU::new();
-        let chars: &[u8] = &include!("chars.in");
-        assert_eq!(&cpu.ram[0..chars.len()], chars);
+        assert_eq!(cpu.ram[..FONT_LEN], Font::default().glyphs);
+    }
+
+    /// An instruction run from 0x200, with the state before and the expected state after.
+    /// Unlisted registers, memory and stack must be left unchanged.
+    #[derive(Default)]
+    struct Case {
+        name: &'static str,
+        op: u16,
+        v: &'static [(usize, u8)],
+        i: usize,
+        ram: &'static [(usize, u8)],
+        stack: &'static [usize],
+        keys: &'static [u8],
+        timer: u8,
+        /// lit pixels, as (x, y)
+        vram: &'static [(usize, usize)],
+
+        expect_pc: Option<usize>,
+        expect_v: &'static [(usize, u8)],
+        expect_i: Option<usize>,
+        expect_ram: &'static [(usize, u8)],
+        expect_stack: Option<&'static [usize]>,
+        expect_delay: Option<u8>,
+        expect_sound: Option<u8>,
+        expect_vram: Option<&'static [(usize, usize)]>,
+    }
+
+    fn cases() -> Vec<Case> {
+        vec![
+            Case {
+                name: "00E0 clears the screen",
+                op: 0x00e0,
+                vram: &[(0, 0), (63, 31)],
+                expect_vram: Some(&[]),
+                ..Case::default()
+            },
+            Case {
+                name: "00EE returns",
+                op: 0x00ee,
+                stack: &[0x300, 0x400],
+                expect_pc: Some(0x400),
+                expect_stack: Some(&[0x300]),
+                ..Case::default()
+            },
+            Case {
+                name: "1nnn jumps",
+                op: 0x1345,
+                expect_pc: Some(0x345),
+                ..Case::default()
+            },
+            Case {
+                name: "2nnn calls",
+                op: 0x2345,
+                stack: &[0x300],
+                expect_pc: Some(0x345),
+                expect_stack: Some(&[0x300, 0x202]),
+                ..Case::default()
+            },
+            Case {
+                name: "3xnn skips if equal",
+                op: 0x3312,
+                v: &[(3, 0x12)],
+                expect_pc: Some(0x204),
+                ..Case::default()
+            },
+            Case {
+                name: "3xnn doesn't skip if not equal",
+                op: 0x3312,
+                v: &[(3, 0x13)],
+                ..Case::default()
+            },
+            Case {
+                name: "4xnn skips if not equal",
+                op: 0x4312,
+                v: &[(3, 0x13)],
+                expect_pc: Some(0x204),
+                ..Case::default()
+            },
+            Case {
+                name: "4xnn doesn't skip if equal",
+                op: 0x4312,
+                v: &[(3, 0x12)],
+                ..Case::default()
+            },
+            Case {
+                name: "5xy0 skips if equal",
+                op: 0x5ab0,
+                v: &[(0xa, 7), (0xb, 7)],
+                expect_pc: Some(0x204),
+                ..Case::default()
+            },
+            Case {
+                name: "5xy0 doesn't skip if not equal",
+                op: 0x5ab0,
+                v: &[(0xa, 7), (0xb, 8)],
+                ..Case::default()
+            },
+            Case {
+                name: "6xnn loads",
+                op: 0x6e42,
+                expect_v: &[(0xe, 0x42)],
+                ..Case::default()
+            },
+            Case {
+                name: "7xnn adds without carry",
+                op: 0x7102,
+                v: &[(1, 0xff)],
+                expect_v: &[(1, 0x01)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xy0 loads",
+                op: 0x8120,
+                v: &[(2, 9)],
+                expect_v: &[(1, 9)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xy1 ors",
+                op: 0x8121,
+                v: &[(1, 0b1100), (2, 0b1010)],
+                expect_v: &[(1, 0b1110)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xy2 ands",
+                op: 0x8122,
+                v: &[(1, 0b1100), (2, 0b1010)],
+                expect_v: &[(1, 0b1000)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xy3 xors",
+                op: 0x8123,
+                v: &[(1, 0b1100), (2, 0b1010)],
+                expect_v: &[(1, 0b0110)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xy4 adds",
+                op: 0x8124,
+                v: &[(1, 0x10), (2, 0x20), (0xf, 1)],
+                expect_v: &[(1, 0x30), (0xf, 0)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xy4 adds with carry",
+                op: 0x8124,
+                v: &[(1, 0xf0), (2, 0x20)],
+                expect_v: &[(1, 0x10), (0xf, 1)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xy5 subtracts",
+                op: 0x8125,
+                v: &[(1, 0x20), (2, 0x20)],
+                expect_v: &[(1, 0), (0xf, 1)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xy5 subtracts with borrow",
+                op: 0x8125,
+                v: &[(1, 0x10), (2, 0x20), (0xf, 1)],
+                expect_v: &[(1, 0xf0), (0xf, 0)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xy5 reads VF before setting the flag",
+                op: 0x81f5,
+                v: &[(1, 0x20), (0xf, 0x10)],
+                expect_v: &[(1, 0x10), (0xf, 1)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xy4 flag wins over the result",
+                op: 0x8f14,
+                v: &[(1, 0x20), (0xf, 0xf0)],
+                expect_v: &[(0xf, 1)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xy6 shifts right",
+                op: 0x8126,
+                v: &[(1, 0b101)],
+                expect_v: &[(1, 0b10), (0xf, 1)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xy7 subtracts Vx from Vy",
+                op: 0x8127,
+                v: &[(1, 0x10), (2, 0x30)],
+                expect_v: &[(1, 0x20), (0xf, 1)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xy7 subtracts Vx from Vy with borrow",
+                op: 0x8127,
+                v: &[(1, 0x30), (2, 0x10), (0xf, 1)],
+                expect_v: &[(1, 0xe0), (0xf, 0)],
+                ..Case::default()
+            },
+            Case {
+                name: "8xyE shifts left",
+                op: 0x812e,
+                v: &[(1, 0b1100_0001)],
+                expect_v: &[(1, 0b1000_0010), (0xf, 1)],
+                ..Case::default()
+            },
+            Case {
+                name: "9xy0 skips if not equal",
+                op: 0x9ab0,
+                v: &[(0xa, 7), (0xb, 8)],
+                expect_pc: Some(0x204),
+                ..Case::default()
+            },
+            Case {
+                name: "9xy0 doesn't skip if equal",
+                op: 0x9ab0,
+                v: &[(0xa, 7), (0xb, 7)],
+                ..Case::default()
+            },
+            Case {
+                name: "Annn loads I",
+                op: 0xa123,
+                expect_i: Some(0x123),
+                ..Case::default()
+            },
+            Case {
+                name: "Bnnn jumps with offset",
+                op: 0xb300,
+                v: &[(0, 0x42)],
+                expect_pc: Some(0x342),
+                ..Case::default()
+            },
+            Case {
+                name: "Cxnn masks the random byte",
+                op: 0xc500,
+                v: &[(5, 0xff)],
+                expect_v: &[(5, 0)],
+                ..Case::default()
+            },
+            Case {
+                name: "Dxyn draws",
+                op: 0xd012,
+                v: &[(0, 2), (1, 3)],
+                i: 0x300,
+                ram: &[(0x300, 0b1000_0001), (0x301, 0b0100_0000)],
+                expect_v: &[(0xf, 0)],
+                expect_vram: Some(&[(2, 3), (9, 3), (3, 4)]),
+                ..Case::default()
+            },
+            Case {
+                name: "Dxyn collides and wraps",
+                op: 0xd011,
+                v: &[(0, 62), (1, 31)],
+                i: 0x300,
+                ram: &[(0x300, 0b1110_0000)],
+                vram: &[(63, 31), (5, 5)],
+                expect_v: &[(0xf, 1)],
+                expect_vram: Some(&[(5, 5), (62, 31), (0, 31)]),
+                ..Case::default()
+            },
+            Case {
+                name: "Dxyn reads VF before setting the flag",
+                op: 0xdff1,
+                v: &[(0xf, 4)],
+                i: 0x300,
+                ram: &[(0x300, 0b1000_0000)],
+                expect_v: &[(0xf, 0)],
+                expect_vram: Some(&[(4, 4)]),
+                ..Case::default()
+            },
+            Case {
+                name: "Ex9E skips if the key is pressed",
+                op: 0xe49e,
+                v: &[(4, 0xa)],
+                keys: &[0xa],
+                expect_pc: Some(0x204),
+                ..Case::default()
+            },
+            Case {
+                name: "Ex9E doesn't skip if the key isn't pressed",
+                op: 0xe49e,
+                v: &[(4, 0xa)],
+                keys: &[0xb],
+                ..Case::default()
+            },
+            Case {
+                name: "ExA1 skips if the key isn't pressed",
+                op: 0xe4a1,
+                v: &[(4, 0xa)],
+                keys: &[0xb],
+                expect_pc: Some(0x204),
+                ..Case::default()
+            },
+            Case {
+                name: "ExA1 doesn't skip if the key is pressed",
+                op: 0xe4a1,
+                v: &[(4, 0xa)],
+                keys: &[0xa],
+                ..Case::default()
+            },
+            Case {
+                name: "Fx07 loads the delay timer",
+                op: 0xf207,
+                timer: 33,
+                expect_v: &[(2, 33)],
+                ..Case::default()
+            },
+            Case {
+                name: "Fx0A waits for a key",
+                op: 0xf20a,
+                expect_pc: Some(0x200),
+                ..Case::default()
+            },
+            Case {
+                name: "Fx0A loads the pressed key",
+                op: 0xf20a,
+                keys: &[0xc],
+                expect_v: &[(2, 0xc)],
+                ..Case::default()
+            },
+            Case {
+                name: "Fx15 sets the delay timer",
+                op: 0xf215,
+                v: &[(2, 60)],
+                expect_delay: Some(60),
+                ..Case::default()
+            },
+            Case {
+                name: "Fx18 sets the sound timer",
+                op: 0xf218,
+                v: &[(2, 60)],
+                expect_sound: Some(60),
+                ..Case::default()
+            },
+            Case {
+                name: "Fx1E adds to I",
+                op: 0xf21e,
+                v: &[(2, 0x10)],
+                i: 0x300,
+                expect_i: Some(0x310),
+                ..Case::default()
+            },
+            Case {
+                name: "Fx1E sets VF when I goes past 0xF00",
+                op: 0xf21e,
+                v: &[(2, 0x10)],
+                i: 0xef8,
+                expect_i: Some(0xf08),
+                expect_v: &[(0xf, 1)],
+                ..Case::default()
+            },
+            Case {
+                name: "Fx29 points I to a glyph",
+                op: 0xf229,
+                v: &[(2, 0xa)],
+                expect_i: Some(50),
+                ..Case::default()
+            },
+            Case {
+                name: "Fx33 stores the decimal digits",
+                op: 0xf233,
+                v: &[(2, 198)],
+                i: 0x300,
+                expect_ram: &[(0x300, 1), (0x301, 9), (0x302, 8)],
+                ..Case::default()
+            },
+            Case {
+                name: "Fx55 stores the registers",
+                op: 0xf255,
+                v: &[(0, 1), (1, 2), (2, 3), (3, 4)],
+                i: 0x300,
+                expect_ram: &[(0x300, 1), (0x301, 2), (0x302, 3)],
+                ..Case::default()
+            },
+            Case {
+                name: "Fx65 loads the registers",
+                op: 0xf265,
+                i: 0x300,
+                ram: &[(0x300, 1), (0x301, 2), (0x302, 3), (0x303, 4)],
+                expect_v: &[(0, 1), (1, 2), (2, 3)],
+                ..Case::default()
+            },
+            Case {
+                name: "unknown instructions are skipped",
+                op: 0x5121,
+                ..Case::default()
+            },
+        ]
+    }
+
+    fn run_case(case: &Case, preset: &str, quirks: Quirks) {
+        let name = format!("{}: {}", preset, case.name);
+        let mut cpu = CPU::with_quirks(quirks);
+        for &(x, value) in case.v {
+            cpu.v[x] = value;
+        }
+        cpu.i = case.i;
+        for &(address, value) in case.ram {
+            cpu.ram[address] = value;
+        }
+        cpu.stack = case.stack.to_vec();
+        for &key in case.keys {
+            cpu.set_key_press(key, true);
+        }
+        cpu.delay = case.timer;
+        for &(x, y) in case.vram {
+            cpu.vram.set(x, y, true);
+        }
+        cpu.mem_cpy(&case.op.to_be_bytes(), PGM_OFFSET);
+
+        let mut v = cpu.v;
+        for &(x, value) in case.expect_v {
+            v[x] = value;
+        }
+        let mut ram = cpu.ram.clone();
+        for &(address, value) in case.expect_ram {
+            ram[address] = value;
+        }
+        let mut vram = Framebuffer::new();
+        for &(x, y) in case.expect_vram.unwrap_or(case.vram) {
+            vram.set(x, y, true);
+        }
+
+        cpu.tick();
+        if cpu.waiting_vblank() {
+            // with display_wait, the instruction only completes after the vertical blank.
+            assert_eq!(cpu.pc, PGM_OFFSET, "{}", name);
+            cpu.vblank();
+            cpu.tick();
+        }
+
+        assert_eq!(cpu.pc, case.expect_pc.unwrap_or(0x202), "pc: {}", name);
+        assert_eq!(cpu.v, v, "v: {}", name);
+        assert_eq!(cpu.i, case.expect_i.unwrap_or(case.i), "i: {}", name);
+        assert!(cpu.ram[..] == ram[..], "ram: {}", name);
+        let stack = case.expect_stack.unwrap_or(case.stack);
+        assert_eq!(cpu.stack, stack, "stack: {}", name);
+        let delay = case.expect_delay.unwrap_or(case.timer);
+        assert_eq!(cpu.delay, delay, "delay: {}", name);
+        assert_eq!(cpu.sound, case.expect_sound.unwrap_or(0), "sound: {}", name);
+        assert_eq!(cpu.vram.rows(), vram.rows(), "vram: {}", name);
+    }
+
+    #[test]
+    fn test_opcodes() {
+        for (preset, quirks) in Quirks::presets().iter() {
+            for case in cases() {
+                run_case(&case, preset, *quirks);
+            }
+        }
     }
 
     #[test]
@@ -513,4 +1404,304 @@ mod tests {
         cpu.tick();
         assert_eq!(cpu.v[0xf], 1);
     }
+
+    #[test]
+    fn test_display_wait() {
+        let mut cpu = CPU::with_quirks(Quirks::vip());
+        // 0x200: DRW V0, V0, 1; 0x202: DRW V0, V0, 1; 0x204: JMP 0x204
+        cpu.mem_cpy(&[0xd0, 0x01, 0xd0, 0x01, 0x12, 0x04], 0x200);
+        cpu.i = 0x600;
+        cpu.ram[0x600] = 0x80;
+
+        // the first frame stalls on the first draw.
+        assert_eq!(cpu.run_frame(10).ticks, 1);
+        assert_eq!(cpu.pc, 0x200);
+        assert!(!cpu.vram.get(0, 0));
+
+        // the next one draws, then stalls on the second draw.
+        assert_eq!(cpu.run_frame(10).ticks, 2);
+        assert_eq!(cpu.pc, 0x202);
+        assert!(cpu.vram.get(0, 0));
+
+        assert_eq!(cpu.run_frame(10).ticks, 10);
+        assert_eq!(cpu.pc, 0x204);
+        assert!(!cpu.vram.get(0, 0));
+    }
+
+    #[test]
+    fn test_vip_timing() {
+        let mut cpu = CPU::new();
+        cpu.timing = Timing::Vip;
+        // 0x200: ADD V0, 1; 0x202: JMP 0x200
+        cpu.mem_cpy(&[0x70, 0x01, 0x12, 0x00], 0x200);
+        // 50 + 52 cycles per loop, out of 2598 per frame.
+        assert_eq!(cpu.run_frame(10).ticks, 51);
+        assert_eq!(cpu.v[0], 26);
+
+        // a clear takes longer than a frame, the time it runs over is taken from the next one.
+        let mut cpu = CPU::new();
+        cpu.timing = Timing::Vip;
+        // 0x200: CLS; 0x202: CLS; 0x204: JMP 0x204
+        cpu.mem_cpy(&[0x00, 0xe0, 0x00, 0xe0, 0x12, 0x04], 0x200);
+        assert_eq!(cpu.run_frame(10).ticks, 1);
+        assert_eq!(cpu.run_frame(10).ticks, 1);
+        assert_eq!(cpu.pc, 0x204);
+        // 2 * 2598 - 2 * 3118 cycles are left, for 30 jumps.
+        assert_eq!(cpu.run_frame(10).ticks, 30);
+    }
+
+    #[test]
+    fn test_op_cxnn_reproducible() {
+        let mut cpu = CPU::new();
+        cpu.rng = Rng::new(RngModel::Xorshift, 1234);
+        let mut values = Vec::new();
+        for _ in 0..8 {
+            cpu.op_cxnn(0, 0xff);
+            values.push(cpu.v[0]);
+        }
+        cpu.reset();
+        for value in values {
+            cpu.op_cxnn(0, 0xff);
+            assert_eq!(cpu.v[0], value);
+        }
+        cpu.op_cxnn(1, 0x0f);
+        assert_eq!(cpu.v[1] & 0xf0, 0);
+    }
+
+    #[test]
+    fn test_broken_roms() {
+        // stack underflow
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0x00, 0xee]);
+        cpu.tick();
+        assert_eq!(cpu.pc, 0x202);
+
+        // stack overflow: 0x200: CALL 0x200
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0x22, 0x00]);
+        for _ in 0..32 {
+            cpu.tick();
+        }
+        assert_eq!(cpu.stack.len(), STACK_SIZE);
+
+        // the pc, I and the keys wrap around.
+        let mut cpu = CPU::new();
+        cpu.pc = 0xffe;
+        cpu.ram[0xffe] = 0xef;
+        cpu.ram[0xfff] = 0x9e;
+        cpu.v[0xf] = 0xff;
+        cpu.set_key_press(0xff, true);
+        cpu.tick();
+        assert_eq!(cpu.pc, 0x002);
+        cpu.i = 0xfff;
+        cpu.v[0] = 123;
+        cpu.op_fx33(0);
+        assert_eq!(cpu.ram[0x000..0x002], [2, 3]);
+
+        // the rom is truncated to the memory.
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0x12; 8192]);
+        assert_eq!(cpu.ram[0xfff], 0x12);
+    }
+
+    #[test]
+    fn test_decode_cache() {
+        let mut cpu = CPU::new();
+        cpu.set_decode_cache(true);
+        // 0x200: LD I, 0x206; 0x202: LD [I], V1; 0x204: JMP 0x200; 0x206: CLS
+        cpu.load_rom(&[0xa2, 0x06, 0xf1, 0x55, 0x12, 0x00, 0x00, 0xe0]);
+        cpu.pc = 0x206;
+        cpu.tick();
+
+        // V0 V1 overwrite the cached CLS with JMP 0x206
+        cpu.v[0] = 0x12;
+        cpu.v[1] = 0x06;
+        cpu.pc = 0x200;
+        for _ in 0..3 {
+            cpu.tick();
+        }
+        assert_eq!(cpu.pc, 0x200);
+        cpu.vram.set(0, 0, true);
+        cpu.pc = 0x206;
+        cpu.tick();
+        assert_eq!(cpu.pc, 0x206);
+        assert!(cpu.vram.get(0, 0));
+
+        // reset keeps the cache enabled.
+        cpu.reset();
+        assert!(cpu.has_decode_cache());
+    }
+
+    #[test]
+    fn test_platforms() {
+        // hi-res: the jump to the interpreter goes to the program, the screen is 64 rows high.
+        let mut cpu = CPU::new();
+        cpu.set_platform(Platform::HiRes);
+        cpu.load_rom(&[0x12, 0x60]);
+        // 0x2c0: LD V1, 40; 0x2c2: DRW V0, V1, 1; 0x2c4: CLS
+        cpu.mem_cpy(&[0x61, 40, 0xd0, 0x11, 0x02, 0x30], 0x2c0);
+        cpu.i = 0x600;
+        cpu.ram[0x600] = 0x80;
+        cpu.tick();
+        assert_eq!(cpu.pc, 0x2c0);
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(cpu.display_size(), (64, 64));
+        assert!(cpu.vram.get(0, 40));
+        cpu.tick();
+        assert!(!cpu.vram.get(0, 40));
+
+        // ETI-660: programs at 0x600, which a reset keeps.
+        let mut cpu = CPU::new();
+        cpu.set_platform(Platform::Eti660);
+        cpu.load_rom(&[0x12, 0x34]);
+        cpu.reset();
+        assert_eq!((cpu.pc, cpu.ram[0x600]), (0x600, 0));
+        assert_eq!(cpu.display_size(), (64, 48));
+
+        // CHIP-8X: colours of 3 bits added separately, and set by zones.
+        let mut cpu = CPU::new();
+        cpu.set_platform(Platform::Chip8X);
+        assert_eq!(cpu.pc, 0x300);
+        // 0x300: ADD V0 V1 colours; 0x302: COLOR V2, V4, 2
+        cpu.load_rom(&[0x50, 0x11, 0xb2, 0x42]);
+        cpu.v[0] = 0x36;
+        cpu.v[1] = 0x13;
+        cpu.v[2] = 8;
+        cpu.v[3] = 1;
+        cpu.v[4] = 2;
+        cpu.tick();
+        assert_eq!(cpu.v[0], 0x41);
+        cpu.tick();
+        cpu.vram.set(8, 1, true);
+        cpu.vram.set(8, 3, true);
+        let mut buffer = vec![(0, 0, 0); 64 * 32];
+        cpu.get_vram_buffer(&mut buffer);
+        assert_eq!(buffer[64 + 8], (0x00, 0x00, 0xff));
+        assert_eq!(buffer[3 * 64 + 8], (0xff, 0x00, 0x00));
+        assert_eq!(buffer[2 * 64 + 8], (0x00, 0x00, 0x80));
+    }
+
+    #[test]
+    fn test_megachip() {
+        let mut cpu = CPU::new();
+        cpu.set_platform(Platform::MegaChip);
+        assert_eq!(cpu.display_size(), (256, 192));
+        cpu.load_rom(&[
+            0x00, 0x11, // MEGAON
+            0x01, 0x01, 0x00, 0x00, // LD I, 0x010000
+            0x02, 0x01, // LD PALETTE, 1
+            0x03, 0x02, // SPRITE WIDTH 2
+            0x04, 0x01, // SPRITE HEIGHT 1
+            0x09, 0x01, // COLLISION 1
+            0x01, 0x01, 0x00, 0x04, // LD I, 0x010004
+            0xd0, 0x11, // DRW V0, V1
+            0xd0, 0x11, // DRW V0, V1
+            0x00, 0xe0, // CLS
+        ]);
+        // an opaque red, and a sprite of it with a transparent pixel.
+        cpu.mem_cpy(&[0xff, 0xff, 0x00, 0x00, 0x01, 0x00], 0x10000);
+        cpu.v[0] = 3;
+        cpu.v[1] = 2;
+        for _ in 0..9 {
+            cpu.tick();
+        }
+        assert_eq!(cpu.i, 0x10004);
+        assert_eq!(cpu.v[0xf], 1);
+        assert_eq!(cpu.take_dirty(), Some(Rect::full(256, 192)));
+        let mut buffer = vec![(0, 0, 0); 256 * 192];
+        cpu.get_vram_buffer(&mut buffer);
+        assert_eq!(buffer[2 * 256 + 3], (0, 0, 0));
+
+        // the clear shows what was drawn.
+        cpu.tick();
+        cpu.get_vram_buffer(&mut buffer);
+        assert_eq!(buffer[2 * 256 + 3], (0xff, 0x00, 0x00));
+        assert_eq!(buffer[2 * 256 + 4], (0, 0, 0));
+
+        // 120Hz, 2 samples: 0x00 and 0xff.
+        cpu.mem_cpy(&[0x00, 0x78, 0x00, 0x00, 0x02, 0x00, 0x00, 0xff], 0x10008);
+        cpu.i = 0x10008;
+        cpu.step(Instruction::PlaySample(false));
+        assert_eq!(cpu.take_samples(), Some(vec![-1.0, 127.0 / 128.0]));
+        assert_eq!(cpu.take_samples(), None);
+    }
+
+    #[test]
+    fn test_user_flags() {
+        let mut cpu = CPU::new();
+        // 0x200: LD [flags], V2; 0x202: LD V1, [flags]
+        cpu.load_rom(&[0xf2, 0x75, 0xf1, 0x85]);
+        cpu.v[..4].copy_from_slice(&[1, 2, 3, 4]);
+        cpu.tick();
+        assert_eq!(cpu.user_flags()[..4], [1, 2, 3, 0]);
+
+        // the flags outlive a reset.
+        cpu.reset();
+        cpu.load_rom(&[0xf2, 0x75, 0xf1, 0x85]);
+        cpu.pc = 0x202;
+        cpu.tick();
+        assert_eq!(cpu.v[..3], [1, 2, 0]);
+    }
+
+    #[test]
+    fn test_font() {
+        let mut cpu = CPU::new();
+        let font = Font {
+            address: 0x50,
+            ..Font::new(FontSet::Vip)
+        };
+        cpu.set_font(font.clone()).unwrap();
+        assert_eq!(cpu.ram[..0x50], [0; 0x50]);
+        assert_eq!(cpu.ram[0x50..0x50 + FONT_LEN], font.glyphs);
+
+        // 0x200: LD F, V1; 0x202: LD HF, V1
+        cpu.load_rom(&[0xf1, 0x29, 0xf1, 0x30]);
+        cpu.v[1] = 3;
+        cpu.tick();
+        assert_eq!(cpu.i, 0x50 + 15);
+        cpu.tick();
+        assert_eq!(cpu.i, 0x50 + 80 + 30);
+
+        // the font outlives a reset.
+        cpu.reset();
+        assert_eq!(cpu.font, font);
+        assert_eq!(cpu.ram[0x50..0x50 + FONT_LEN], font.glyphs);
+
+        // the fonts must fit in the memory.
+        let font = Font {
+            address: 0x1000 - FONT_LEN + 1,
+            ..Font::default()
+        };
+        assert!(cpu.set_font(font).is_err());
+        assert_eq!(cpu.font.address, 0x50);
+
+        // a program written over the old font keeps its bytes.
+        cpu.ram[0x50..0x54].copy_from_slice(&[0x12, 0x34, 0xf0, 0x60]);
+        let font = Font {
+            address: 0x100,
+            ..Font::default()
+        };
+        cpu.set_font(font).unwrap();
+        assert_eq!(cpu.ram[0x50..0x54], [0x12, 0x34, 0xf0, 0x60]);
+        assert_eq!(cpu.ram[0x54..0x100], [0; 0xac]);
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut cpu = CPU::new();
+        // 0x200: JMP 0x200
+        cpu.ram[0x200] = 0x12;
+        cpu.ram[0x201] = 0x00;
+        cpu.delay = 2;
+        cpu.sound = 1;
+        let frame = cpu.run_frame(10);
+        assert_eq!(frame.ticks, 10);
+        assert!(frame.beep);
+        assert_eq!(cpu.delay, 1);
+        assert_eq!(cpu.sound, 0);
+        assert!(!cpu.run_frame(10).beep);
+        assert_eq!(cpu.delay, 0);
+        assert_eq!(cpu.sound, 0);
+    }
 }
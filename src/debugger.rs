@@ -1,4 +1,7 @@
-use crate::cpu::{Processor, CPU};
+use crate::cpu::{Frame, Processor, CPU};
+use crate::flags::FLAG_COUNT;
+use crate::framebuffer::Rect;
+use crate::instruction::Instruction;
 
 pub struct Debugger {
     pub cpu: CPU,
@@ -11,72 +14,83 @@ impl Debugger {
 
     fn print_state(&self) {
         println!(
-            "\x1b[2Jpc: 0x{:02x}\ni: 0x{:03x}\nregisters: {:?}\nkeys: {:?}\nop: 0x{:04x} {}",
+            "\x1b[2Jpc: 0x{:02x}\ni: 0x{:03x}\nregisters: {:?}\nkeys: {:?}\nrng: {:?}\nop: 0x{:04x} {}",
             self.cpu.pc,
             self.cpu.i,
             self.cpu.v,
             self.cpu.key_press,
-            (self.cpu.ram[self.cpu.pc] as u16) << 8 | self.cpu.ram[self.cpu.pc + 1] as u16,
+            self.cpu.rng,
+            (self.cpu.ram[self.cpu.pc] as u16) << 8 | self.cpu.ram[(self.cpu.pc + 1) & 0xfff] as u16,
             self.get_op()
         )
     }
 
     fn get_op(&self) -> String {
-        let hi = self.cpu.ram[self.cpu.pc] as u16;
-        let lo = self.cpu.ram[self.cpu.pc + 1] as u16;
-        let inst = hi << 8 | lo;
-
-        let nibs = (
-            (inst & 0xf000) >> 12,
-            (inst & 0x0f00) >> 8,
-            (inst & 0x00f0) >> 4,
-            (inst & 0x000f),
-        );
-
-        let nnn = (inst & 0x0fff) as usize;
-        let nn = (inst & 0x00ff) as u8;
-        let n = nibs.3 as u8;
-        let x = nibs.1 as usize;
-        let y = nibs.2 as usize;
-
-        println!("inst: {:?}", nibs);
-        match nibs {
-            (0x00, 0x00, 0x0e, 0x0e) => format!("RET"),
-            (0x00, 0x00, 0x0e, 0x00) => format!("CLS"),
-            (0x01, _, _, _) => format!("JMP\t0x{:03x}", nnn),
-            (0x02, _, _, _) => format!("CALL\t{:03x}", nnn),
-            (0x03, _, _, _) => format!("SE\tV{:x}, {}", x, nn),
-            (0x04, _, _, _) => format!("SNE\t V{:x}, {:02x}", x, nn),
-            (0x05, _, _, 0x00) => format!("SE\tV{:x}, V{:x}", x, y),
-            (0x06, _, _, _) => format!("LD\tV{:x}, {:02x}", x, nn),
-            (0x07, _, _, _) => format!("ADD\tV{:x}, {:02x}", x, nn),
-            (0x08, _, _, 0x00) => format!("LD\tV{:x}, V{:x}", x, y),
-            (0x08, _, _, 0x01) => format!("OR\tV{:x}, V{:x}", x, y),
-            (0x08, _, _, 0x02) => format!("AND\tV{:x}, V{:x}", x, y),
-            (0x08, _, _, 0x03) => format!("XOR\tV{:x}, V{:x}", x, y),
-            (0x08, _, _, 0x04) => format!("ADD\tV{:x}, V{:x}", x, y),
-            (0x08, _, _, 0x05) => format!("SUB\tV{:x}, V{:x}", x, y),
-            (0x08, _, _, 0x06) => format!("SHR\tV{:x}, {{V{:x}}}", x, y),
-            (0x08, _, _, 0x07) => format!("SUBN\tV{:x}, V{:x}", x, y),
-            (0x08, _, _, 0x0e) => format!("SHL\tV{:x}, {{V{:02x}}}", x, y),
-            (0x09, _, _, 0x00) => format!("SNE\tV{:x}, V{:02x}", x, y),
-            (0x0a, _, _, _) => format!("LD\t I, 0x{:03x}", nnn),
-            (0x0b, _, _, _) => format!("JP\tV0, 0x{:03x}", nnn),
-            (0x0c, _, _, _) => format!("RND\tV{:x}, 0x{:03x}", x, nn),
-            (0x0d, _, _, _) => format!("DRW\tV{:x}, V{:x}, 0x{:02x}", x, y, n),
-            (0x0e, _, 0x09, 0x0e) => format!("SKP\tV{:x}", x),
-            (0x0e, _, 0x0a, 0x01) => format!("SKNP\tV{:x}", x),
-            (0x0f, _, 0x00, 0x07) => format!("LD\tV{:x}, Dt", x),
-            (0x0f, _, 0x00, 0x0a) => format!("LD\tV{:x}, K", x),
-            (0x0f, _, 0x01, 0x05) => format!("LD\tDt, V{:x}", x),
-            (0x0f, _, 0x01, 0x08) => format!("LD\tST, V{:x}", x),
-            (0x0f, _, 0x01, 0x0e) => format!("ADD\tI, V{:x}", x),
-            (0x0f, _, 0x02, 0x09) => format!("LD\tF, V{:x}", x),
-            (0x0f, _, 0x03, 0x03) => format!("LD\tB, V{:x}", x),
-            (0x0f, _, 0x05, 0x05) => format!("LD\t[I], V{:x}", x),
-            (0x0f, _, 0x06, 0x05) => format!("LD\tV{:x}, [I]", x),
-            _ => String::new(),
+        mnemonic(self.cpu.decode_at(self.cpu.pc))
+    }
+}
+
+/// The assembly of an instruction, as the debugger shows it.
+fn mnemonic(inst: Instruction) -> String {
+    match inst {
+        Instruction::Cls => "CLS".to_string(),
+        Instruction::Ret => "RET".to_string(),
+        Instruction::Jp(nnn) => format!("JMP\t0x{:03x}", nnn),
+        Instruction::Call(nnn) => format!("CALL\t0x{:03x}", nnn),
+        Instruction::SeImm(x, nn) => format!("SE\tV{:x}, 0x{:02x}", x, nn),
+        Instruction::SneImm(x, nn) => format!("SNE\tV{:x}, 0x{:02x}", x, nn),
+        Instruction::Se(x, y) => format!("SE\tV{:x}, V{:x}", x, y),
+        Instruction::LdImm(x, nn) => format!("LD\tV{:x}, 0x{:02x}", x, nn),
+        Instruction::AddImm(x, nn) => format!("ADD\tV{:x}, 0x{:02x}", x, nn),
+        Instruction::Ld(x, y) => format!("LD\tV{:x}, V{:x}", x, y),
+        Instruction::Or(x, y) => format!("OR\tV{:x}, V{:x}", x, y),
+        Instruction::And(x, y) => format!("AND\tV{:x}, V{:x}", x, y),
+        Instruction::Xor(x, y) => format!("XOR\tV{:x}, V{:x}", x, y),
+        Instruction::Add(x, y) => format!("ADD\tV{:x}, V{:x}", x, y),
+        Instruction::Sub(x, y) => format!("SUB\tV{:x}, V{:x}", x, y),
+        Instruction::Shr(x, y) => format!("SHR\tV{:x}, {{V{:x}}}", x, y),
+        Instruction::Subn(x, y) => format!("SUBN\tV{:x}, V{:x}", x, y),
+        Instruction::Shl(x, y) => format!("SHL\tV{:x}, {{V{:x}}}", x, y),
+        Instruction::Sne(x, y) => format!("SNE\tV{:x}, V{:x}", x, y),
+        Instruction::LdI(nnn) => format!("LD\tI, 0x{:03x}", nnn),
+        Instruction::JpV0(nnn) => format!("JP\tV0, 0x{:03x}", nnn),
+        Instruction::Rnd(x, nn) => format!("RND\tV{:x}, 0x{:02x}", x, nn),
+        Instruction::Drw(x, y, n) => format!("DRW\tV{:x}, V{:x}, 0x{:x}", x, y, n),
+        Instruction::Skp(x) => format!("SKP\tV{:x}", x),
+        Instruction::Sknp(x) => format!("SKNP\tV{:x}", x),
+        Instruction::LdVxDt(x) => format!("LD\tV{:x}, DT", x),
+        Instruction::LdVxK(x) => format!("LD\tV{:x}, K", x),
+        Instruction::LdDtVx(x) => format!("LD\tDT, V{:x}", x),
+        Instruction::LdStVx(x) => format!("LD\tST, V{:x}", x),
+        Instruction::AddI(x) => format!("ADD\tI, V{:x}", x),
+        Instruction::LdF(x) => format!("LD\tF, V{:x}", x),
+        Instruction::LdHf(x) => format!("LD\tHF, V{:x}", x),
+        Instruction::LdB(x) => format!("LD\tB, V{:x}", x),
+        Instruction::LdMemVx(x) => format!("LD\t[I], V{:x}", x),
+        Instruction::LdVxMem(x) => format!("LD\tV{:x}, [I]", x),
+        Instruction::LdFlagsVx(x) => format!("LD\tR, V{:x}", x),
+        Instruction::LdVxFlags(x) => format!("LD\tV{:x}, R", x),
+        Instruction::BgColor => "BGCOL".to_string(),
+        Instruction::AddColors(x, y) => format!("ADD\tC{:x}, C{:x}", x, y),
+        Instruction::Color(x, y, n) => format!("COL\tV{:x}, V{:x}, 0x{:x}", x, y, n),
+        Instruction::SkpPad2(x) => format!("SKP2\tV{:x}", x),
+        Instruction::SknpPad2(x) => format!("SKNP2\tV{:x}", x),
+        Instruction::Out(x) => format!("OUT\tV{:x}", x),
+        Instruction::In(x) => format!("IN\tV{:x}", x),
+        Instruction::MegaOff => "MEGAOFF".to_string(),
+        Instruction::MegaOn => "MEGAON".to_string(),
+        Instruction::LdIFar(nn) => format!("LDHI\tI, 0x{:02x}....", nn),
+        Instruction::LdPalette(nn) => format!("LDPAL\t0x{:02x}", nn),
+        Instruction::SpriteWidth(nn) => format!("SPRW\t0x{:02x}", nn),
+        Instruction::SpriteHeight(nn) => format!("SPRH\t0x{:02x}", nn),
+        Instruction::ScreenAlpha(nn) => format!("ALPHA\t0x{:02x}", nn),
+        Instruction::PlaySample(looped) => {
+            format!("DIGISND\t{}", if looped { "loop" } else { "once" })
         }
+        Instruction::StopSample => "STOPSND".to_string(),
+        Instruction::BlendMode(n) => format!("BMODE\t0x{:x}", n),
+        Instruction::CollisionColor(nn) => format!("CCOL\t0x{:02x}", nn),
+        Instruction::Unknown => String::new(),
     }
 }
 
@@ -88,8 +102,40 @@ impl Processor for Debugger {
         self.cpu.load_rom(rom);
     }
 
-    fn get_vram_buffer(&self, buffer: &mut [(u8, u8, u8)]) {
-        self.cpu.get_vram_buffer(buffer)
+    fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    fn tick_timers(&mut self) {
+        self.cpu.tick_timers();
+    }
+
+    fn vblank(&mut self) {
+        self.cpu.vblank();
+    }
+
+    fn waiting_vblank(&self) -> bool {
+        self.cpu.waiting_vblank()
+    }
+
+    fn display_size(&self) -> (usize, usize) {
+        self.cpu.display_size()
+    }
+
+    fn take_samples(&mut self) -> Option<Vec<f32>> {
+        self.cpu.take_samples()
+    }
+
+    fn user_flags(&self) -> [u8; FLAG_COUNT] {
+        self.cpu.user_flags()
+    }
+
+    fn set_user_flags(&mut self, flags: [u8; FLAG_COUNT]) {
+        self.cpu.set_user_flags(flags);
+    }
+
+    fn get_vram_region(&self, buffer: &mut [(u8, u8, u8)], rect: Rect) {
+        self.cpu.get_vram_region(buffer, rect)
     }
 
     fn set_key_press(&mut self, key: u8, is_down: bool) {
@@ -100,13 +146,40 @@ impl Processor for Debugger {
         self.cpu.should_redraw()
     }
 
-    fn drawn(&mut self) {
-        self.cpu.drawn();
+    fn take_dirty(&mut self) -> Option<Rect> {
+        self.cpu.take_dirty()
     }
 
     fn tick(&mut self) {
         self.print_state();
         self.cpu.tick();
     }
+
+    fn run_frame(&mut self, ipf: u32) -> Frame {
+        self.cpu.start_frame(ipf);
+        let mut ticks = 0;
+        while self.cpu.has_cycles() && !self.waiting_vblank() {
+            self.tick();
+            ticks += 1;
+        }
+        let beep = self.get_sound_timer() > 0;
+        self.tick_timers();
+        self.vblank();
+        Frame { ticks, beep }
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+
+    #[test]
+    fn test_get_op() {
+        let mut cpu = CPU::new();
+        cpu.set_platform(Platform::Chip8X);
+        cpu.load_rom(&[0xb1, 0x23]);
+        let debugger = Debugger::new(cpu);
+        assert_eq!(debugger.get_op(), "COL\tV1, V2, 0x3");
+    }
+}
@@ -0,0 +1,540 @@
+use crate::audio::SoundQueue;
+use crate::config::*;
+use crate::cpu::Processor;
+use crate::filter::{Filter, FrameFilter};
+use crate::flags::FLAG_COUNT;
+use crate::hash::fnv1a;
+use crate::movie::{KeyEvent, Movie};
+use crate::speed::Speed;
+use crate::wav::WavWriter;
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// the timers, and therefore the frames, run at 60Hz.
+pub const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+// frames of audio queued ahead of the playback, which is the audio latency.
+const AUDIO_LATENCY_FRAMES: usize = 3;
+
+/// The requests sent to the emulation thread, handled in order between frames.
+pub enum Command {
+    Load(Vec<u8>),
+    /// Sets the RPL user flags, as saved for the rom.
+    SetFlags([u8; FLAG_COUNT]),
+    Key(u8, bool),
+    Reset,
+    Pause,
+    Resume,
+    /// Runs a single frame, only while paused.
+    Step,
+    SetIpf(u32),
+    SetSpeed(Speed),
+    SetTurbo(bool),
+    SetAudioSync(bool),
+    SetFilter(Filter),
+    RecordAudio(WavWriter<BufWriter<File>>),
+    /// Records the keypad input to the movie, saved to the path on `Stop`.
+    RecordMovie(Movie, String),
+    PlayMovie(Movie),
+    Stop,
+}
+
+/// Sent by the emulation thread when a frame is ready to be presented, or the stats changed.
+pub struct Update {
+    /// The filtered screen, if it changed since the last update.
+    pub frame: Option<Vec<(u8, u8, u8)>>,
+    pub frames: u64,
+    pub ips: u64,
+    /// The RPL user flags, if they changed since the last update.
+    pub flags: Option<[u8; FLAG_COUNT]>,
+}
+
+/// The emulation thread, running the processor at its own pace whatever the frontend does.
+pub struct Handle {
+    commands: Sender<Command>,
+    pub updates: Receiver<Update>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Handle {
+    /// Starts the emulation of `cpu`, which plays its sound through `sound_queue`.
+    pub fn spawn<T: Processor + Send + 'static>(cpu: T, sound_queue: SoundQueue) -> Self {
+        let (commands, receiver) = mpsc::channel();
+        let (sender, updates) = mpsc::channel();
+        let emulator = Emulator::new(cpu, sound_queue, sender);
+        let thread = thread::Builder::new()
+            .name("emulation".to_string())
+            .spawn(move || emulator.run(receiver))
+            .expect("could not start the emulation thread");
+        Self {
+            commands,
+            updates,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn send(&self, command: Command) {
+        // if the thread is gone, the updates channel tells the frontend.
+        let _ = self.commands.send(command);
+    }
+
+    /// Stops the emulation and waits for the recordings to be saved.
+    pub fn stop(&mut self) {
+        self.send(Command::Stop);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                eprintln!("the emulation thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+struct Emulator<T: Processor> {
+    cpu: T,
+    rom: Vec<u8>,
+    sound_queue: SoundQueue,
+    audio_sync: bool,
+    wav: Option<WavWriter<BufWriter<File>>>,
+    // the movie being recorded and the file it is saved to.
+    recording: Option<(Movie, String)>,
+    // the movie being played back and the index of its next event.
+    playback: Option<(Movie, usize)>,
+    // the screen of the processor, only updated where it changed, and its filtered version.
+    screen: Vec<(u8, u8, u8)>,
+    frame: Vec<(u8, u8, u8)>,
+    frame_dirty: bool,
+    filter: FrameFilter,
+    // the user flags last reported to the frontend, and whether they changed since.
+    flags: [u8; FLAG_COUNT],
+    flags_dirty: bool,
+    paused: bool,
+    ipf: u32,
+    frames: u64,
+    speed: Speed,
+    turbo: bool,
+    last_update: Instant,
+    ips: IpsCounter,
+    updates: Sender<Update>,
+}
+
+struct IpsCounter {
+    ticks: u64,
+    since: Instant,
+    ips: u64,
+}
+
+impl IpsCounter {
+    fn new() -> Self {
+        Self {
+            ticks: 0,
+            since: Instant::now(),
+            ips: 0,
+        }
+    }
+
+    // Counts executed instructions, returns true when the measured value was updated.
+    fn count(&mut self, ticks: u32) -> bool {
+        self.ticks += ticks as u64;
+        let elapsed = self.since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.ips = (self.ticks as f64 / elapsed.as_secs_f64()) as u64;
+            self.ticks = 0;
+            self.since = Instant::now();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<T: Processor> Emulator<T> {
+    fn new(cpu: T, sound_queue: SoundQueue, updates: Sender<Update>) -> Self {
+        let (width, height) = cpu.display_size();
+        Self {
+            cpu,
+            rom: Vec::new(),
+            sound_queue,
+            audio_sync: false,
+            wav: None,
+            recording: None,
+            playback: None,
+            screen: vec![BG_COLOR; width * height],
+            frame: vec![BG_COLOR; width * height],
+            frame_dirty: false,
+            filter: FrameFilter::new(Filter::None, width * height),
+            flags: [0; FLAG_COUNT],
+            flags_dirty: false,
+            paused: false,
+            ipf: DEFAULT_IPF,
+            frames: 0,
+            speed: Speed::normal(),
+            turbo: false,
+            last_update: Instant::now(),
+            ips: IpsCounter::new(),
+            updates,
+        }
+    }
+
+    fn run(mut self, commands: Receiver<Command>) {
+        let mut next_frame = Instant::now();
+        loop {
+            // the commands are handled while waiting for the next frame, or for resuming.
+            let command = if self.is_waiting() {
+                commands.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                commands.recv_timeout(next_frame.saturating_duration_since(Instant::now()))
+            };
+            match command {
+                Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                Ok(command) => {
+                    let waiting = self.is_waiting();
+                    self.apply(command);
+                    if waiting && !self.is_waiting() {
+                        next_frame = Instant::now();
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+            }
+
+            self.run_frame();
+            if self.audio_sync && self.effective_speed() == Speed::normal() {
+                // the audio device consumes a frame every 1/60s, wait for it.
+                while self.sound_queue.len() >= AUDIO_LATENCY_FRAMES {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                next_frame = Instant::now();
+                continue;
+            }
+            // the deadline accounts for the time spent emulating.
+            match self.effective_speed().scale(FRAME_TIME) {
+                Some(frame_time) => {
+                    next_frame += frame_time;
+                    let now = Instant::now();
+                    if now > next_frame && now - next_frame > Duration::from_millis(100) {
+                        // too far behind to catch up, don't try to.
+                        next_frame = now;
+                    }
+                }
+                None => next_frame = Instant::now(),
+            }
+        }
+        self.finish();
+    }
+
+    fn apply(&mut self, command: Command) {
+        match command {
+            Command::Load(rom) => {
+                self.rom = rom;
+                self.bind_movies();
+                self.restart();
+            }
+            Command::SetFlags(flags) => {
+                self.cpu.set_user_flags(flags);
+                self.flags = flags;
+            }
+            Command::Key(key, down) => self.set_key(key, down),
+            Command::Reset => self.reset(),
+            Command::Pause => self.paused = true,
+            Command::Resume => self.paused = false,
+            Command::Step => {
+                if self.paused {
+                    self.run_frame();
+                    self.send_update();
+                }
+            }
+            Command::SetIpf(ipf) => self.ipf = ipf,
+            Command::SetSpeed(speed) => self.speed = speed,
+            Command::SetTurbo(turbo) => self.turbo = turbo,
+            Command::SetAudioSync(audio_sync) => self.audio_sync = audio_sync,
+            Command::SetFilter(filter) => self.filter = FrameFilter::new(filter, self.frame.len()),
+            Command::RecordAudio(wav) => self.wav = Some(wav),
+            Command::RecordMovie(mut movie, path) => {
+                movie.events.clear();
+                self.recording = Some((movie, path));
+                if !self.rom.is_empty() {
+                    self.bind_movies();
+                }
+            }
+            Command::PlayMovie(movie) => {
+                self.ipf = movie.ipf;
+                self.playback = Some((movie, 0));
+                if !self.rom.is_empty() {
+                    self.bind_movies();
+                }
+            }
+            Command::Stop => (),
+        }
+    }
+
+    fn set_key(&mut self, key: u8, down: bool) {
+        // while a movie is played back, it owns the keypad.
+        if self.playback.is_some() {
+            return;
+        }
+        self.cpu.set_key_press(key, down);
+        if let Some((movie, _)) = self.recording.as_mut() {
+            movie.events.push(KeyEvent {
+                frame: self.frames,
+                key,
+                down,
+            });
+        }
+    }
+
+    // No frames are run while paused, or before a rom is loaded.
+    fn is_waiting(&self) -> bool {
+        self.paused || self.rom.is_empty()
+    }
+
+    // Reloads the rom and reinitializes the processor, keeping the pause state.
+    fn reset(&mut self) {
+        self.restart();
+        self.render();
+        self.send_update();
+    }
+
+    // Ties the movies to the rom: the recording takes its hash, the playback checks it. The
+    // movies may be set up before the rom is loaded, so that they start with its first frame.
+    fn bind_movies(&mut self) {
+        let hash = fnv1a(&self.rom);
+        if let Some((movie, _)) = self.recording.as_mut() {
+            movie.rom_hash = hash;
+        }
+        if let Some((movie, _)) = &self.playback {
+            if movie.rom_hash != hash {
+                eprintln!("warning: the movie was recorded with a different rom");
+            }
+        }
+    }
+
+    // Starts the rom over from frame 0, the movies with it.
+    fn restart(&mut self) {
+        self.cpu.reset();
+        self.cpu.load_rom(&self.rom);
+        self.frames = 0;
+        // the movies start over with the rom.
+        if let Some((movie, _)) = self.recording.as_mut() {
+            movie.events.clear();
+        }
+        if let Some((_, next)) = self.playback.as_mut() {
+            *next = 0;
+        }
+        self.filter.clear();
+    }
+
+    fn run_frame(&mut self) {
+        self.play_movie_events();
+        let frame = self.cpu.run_frame(self.ipf);
+        self.frames += 1;
+        if self.cpu.should_redraw() || self.filter.is_persistent() {
+            self.render();
+        }
+        let samples = self.cpu.take_samples();
+        // when running faster than the audio plays, drop frames rather than build up latency.
+        if self.sound_queue.len() < AUDIO_LATENCY_FRAMES {
+            match &samples {
+                Some(samples) => self.sound_queue.push_samples(samples.clone()),
+                None => self.sound_queue.push(frame.beep),
+            };
+        }
+        if let Some(wav) = self.wav.as_mut() {
+            let written = match &samples {
+                Some(samples) => wav.push_samples(samples),
+                None => wav.push_frame(frame.beep),
+            };
+            if let Err(e) = written {
+                eprintln!("stopped recording audio: {}", e);
+                self.wav = None;
+            }
+        }
+        let flags = self.cpu.user_flags();
+        if flags != self.flags {
+            self.flags = flags;
+            self.flags_dirty = true;
+        }
+        let ips_updated = self.ips.count(frame.ticks);
+        if (self.frame_dirty && self.update_due()) || ips_updated || self.flags_dirty {
+            self.send_update();
+        }
+    }
+
+    fn play_movie_events(&mut self) {
+        let frames = self.frames;
+        if let Some((movie, next)) = self.playback.as_mut() {
+            while let Some(event) = movie.events.get(*next).filter(|e| e.frame <= frames) {
+                self.cpu.set_key_press(event.key, event.down);
+                *next += 1;
+            }
+            if *next == movie.events.len() {
+                eprintln!("end of the movie, the keypad is back to the keyboard");
+                self.playback = None;
+            }
+        }
+    }
+
+    // Fetches the changed region of the screen from the processor and runs it through the filter.
+    fn render(&mut self) {
+        if let Some(rect) = self.cpu.take_dirty() {
+            self.cpu.get_vram_region(&mut self.screen, rect);
+        }
+        self.frame.copy_from_slice(&self.screen);
+        self.filter.apply(&mut self.frame);
+        self.frame_dirty = true;
+    }
+
+    fn send_update(&mut self) {
+        let frame = if self.frame_dirty {
+            self.frame_dirty = false;
+            self.last_update = Instant::now();
+            Some(self.frame.clone())
+        } else {
+            None
+        };
+        // a closed channel means the frontend is stopping.
+        let _ = self.updates.send(Update {
+            frame,
+            frames: self.frames,
+            ips: self.ips.ips,
+            flags: if std::mem::take(&mut self.flags_dirty) {
+                Some(self.flags)
+            } else {
+                None
+            },
+        });
+    }
+
+    // When running faster than normal, frames are sent at most at the host's 60Hz.
+    fn update_due(&self) -> bool {
+        match self.effective_speed() {
+            Speed::Multiplier(m) if m <= 1.0 => true,
+            _ => self.last_update.elapsed() >= FRAME_TIME,
+        }
+    }
+
+    fn effective_speed(&self) -> Speed {
+        if self.turbo {
+            Speed::Unlimited
+        } else {
+            self.speed
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(wav) = self.wav.take() {
+            if let Err(e) = wav.finish() {
+                eprintln!("could not write the audio recording: {}", e);
+            }
+        }
+        if let Some((movie, path)) = self.recording.take() {
+            if let Err(e) = movie.save(&path) {
+                eprintln!("could not save the movie: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::font::Font;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+    use crate::rng::RngModel;
+    use crate::timing::Timing;
+
+    #[test]
+    fn test_emulation_thread() {
+        let mut emulator = Handle::spawn(CPU::new(), SoundQueue::new());
+        // 0x200: LD V0, 0; 0x202: LD F, V0; 0x204: DRW V0, V0, 5; 0x206: JMP 0x206
+        emulator.send(Command::Load(vec![
+            0x60, 0x00, 0xf0, 0x29, 0xd0, 0x05, 0x12, 0x06,
+        ]));
+        emulator.send(Command::SetSpeed(Speed::Unlimited));
+        let frame = emulator
+            .updates
+            .iter()
+            .find_map(|update| update.frame)
+            .unwrap();
+        // the top of the 0.
+        assert!(frame[..4].iter().all(|&p| p == (0xff, 0xff, 0xff)));
+        assert_eq!(frame[4], BG_COLOR);
+
+        // once stopped, the thread is gone and no more updates come.
+        emulator.stop();
+        emulator.updates.try_iter().for_each(drop);
+        assert!(emulator.updates.recv().is_err());
+    }
+
+    #[test]
+    fn test_load_after_start() {
+        let emulator = Handle::spawn(CPU::new(), SoundQueue::new());
+        // the thread waits for the rom, which starts at frame 0.
+        thread::sleep(Duration::from_millis(20));
+        // 0x200: LD V0, 0; 0x202: LD F, V0; 0x204: DRW V0, V0, 5; 0x206: JMP 0x206
+        emulator.send(Command::Load(vec![
+            0x60, 0x00, 0xf0, 0x29, 0xd0, 0x05, 0x12, 0x06,
+        ]));
+        let update = emulator
+            .updates
+            .iter()
+            .find(|update| update.frame.is_some())
+            .unwrap();
+        assert_eq!(update.frame.unwrap()[0], (0xff, 0xff, 0xff));
+        assert!(update.frames <= 2);
+    }
+
+    #[test]
+    fn test_user_flags() {
+        let emulator = Handle::spawn(CPU::new(), SoundQueue::new());
+        let mut flags = [0; FLAG_COUNT];
+        flags[1] = 7;
+        // 0x200: LD V1, [flags]; 0x202: LD V0, V1; 0x204: LD [flags], V1; 0x206: JMP 0x206
+        // the flags are sent first, as `Chip8::load` does, to be there for the rom's first frame.
+        emulator.send(Command::SetFlags(flags));
+        emulator.send(Command::Load(vec![
+            0xf1, 0x85, 0x80, 0x10, 0xf1, 0x75, 0x12, 0x06,
+        ]));
+        emulator.send(Command::SetSpeed(Speed::Unlimited));
+        let flags = emulator
+            .updates
+            .iter()
+            .find_map(|update| update.flags)
+            .unwrap();
+        assert_eq!(flags[..3], [7, 7, 0]);
+    }
+
+    #[test]
+    fn test_record_before_load() {
+        let path = std::env::temp_dir().join(format!("chip8-movie-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let rom = vec![0x12, 0x00];
+        let mut emulator = Handle::spawn(CPU::new(), SoundQueue::new());
+        let movie = Movie {
+            rom_hash: 0,
+            seed: 0,
+            rng: RngModel::Xorshift,
+            ipf: DEFAULT_IPF,
+            quirks: Quirks::default(),
+            timing: Timing::default(),
+            platform: Platform::default(),
+            font: Font::default(),
+            events: Vec::new(),
+        };
+        emulator.send(Command::RecordMovie(movie, path.to_string()));
+        emulator.send(Command::Load(rom.clone()));
+        emulator.stop();
+        let movie = Movie::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(movie.rom_hash, fnv1a(&rom));
+    }
+}
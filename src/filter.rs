@@ -0,0 +1,103 @@
+/// Post-processing applied to each frame before it is presented, to hide the flicker caused by
+/// roms erasing and redrawing their sprites with XOR.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// The screen is presented as it is at the frame boundary.
+    None,
+    /// Pixels that turn off fade out, keeping `decay` of their brightness on each frame.
+    Blend(f32),
+    /// A pixel is lit if it is lit in the current or the previous frame.
+    Or,
+}
+
+pub struct FrameFilter {
+    filter: Filter,
+    previous: Vec<(u8, u8, u8)>,
+}
+
+impl FrameFilter {
+    pub fn new(filter: Filter, len: usize) -> Self {
+        Self {
+            filter,
+            previous: vec![(0, 0, 0); len],
+        }
+    }
+
+    /// Whether the output changes from frame to frame even if the screen doesn't.
+    pub fn is_persistent(&self) -> bool {
+        self.filter != Filter::None
+    }
+
+    /// Forgets the previous frames.
+    pub fn clear(&mut self) {
+        self.previous.iter_mut().for_each(|p| *p = (0, 0, 0));
+    }
+
+    pub fn apply(&mut self, frame: &mut [(u8, u8, u8)]) {
+        match self.filter {
+            Filter::None => (),
+            Filter::Blend(decay) => {
+                for (pix, prev) in frame.iter_mut().zip(self.previous.iter_mut()) {
+                    let faded = (fade(prev.0, decay), fade(prev.1, decay), fade(prev.2, decay));
+                    *pix = brightest(*pix, faded);
+                    *prev = *pix;
+                }
+            }
+            Filter::Or => {
+                for (pix, prev) in frame.iter_mut().zip(self.previous.iter_mut()) {
+                    let current = *pix;
+                    *pix = brightest(current, *prev);
+                    *prev = current;
+                }
+            }
+        }
+    }
+}
+
+fn fade(c: u8, decay: f32) -> u8 {
+    (c as f32 * decay) as u8
+}
+
+fn brightest(a: (u8, u8, u8), b: (u8, u8, u8)) -> (u8, u8, u8) {
+    (a.0.max(b.0), a.1.max(b.1), a.2.max(b.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ON: (u8, u8, u8) = (0xff, 0xff, 0xff);
+    const OFF: (u8, u8, u8) = (0, 0, 0);
+
+    #[test]
+    fn test_blend() {
+        let mut filter = FrameFilter::new(Filter::Blend(0.5), 2);
+        let mut frame = [ON, OFF];
+        filter.apply(&mut frame);
+        assert_eq!(frame, [ON, OFF]);
+
+        let mut frame = [OFF, ON];
+        filter.apply(&mut frame);
+        assert_eq!(frame, [(0x7f, 0x7f, 0x7f), ON]);
+
+        let mut frame = [OFF, OFF];
+        filter.apply(&mut frame);
+        assert_eq!(frame, [(0x3f, 0x3f, 0x3f), (0x7f, 0x7f, 0x7f)]);
+    }
+
+    #[test]
+    fn test_or() {
+        let mut filter = FrameFilter::new(Filter::Or, 2);
+        let mut frame = [ON, OFF];
+        filter.apply(&mut frame);
+        assert_eq!(frame, [ON, OFF]);
+
+        let mut frame = [OFF, ON];
+        filter.apply(&mut frame);
+        assert_eq!(frame, [ON, ON]);
+
+        let mut frame = [OFF, OFF];
+        filter.apply(&mut frame);
+        assert_eq!(frame, [OFF, ON]);
+    }
+}
@@ -0,0 +1,75 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// The number of RPL user flags: SCHIP has 8, XO-CHIP 16.
+pub const FLAG_COUNT: usize = 16;
+
+/// Where the RPL user flags of each rom are kept between sessions, one file per rom named after
+/// its hash.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlagStore {
+    dir: PathBuf,
+}
+
+impl FlagStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// `~/.chip8/flags`, or `flags` in the working directory without a home.
+    pub fn default_dir() -> PathBuf {
+        match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".chip8").join("flags"),
+            None => PathBuf::from("flags"),
+        }
+    }
+
+    fn path(&self, rom_hash: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.flags", rom_hash))
+    }
+
+    /// The flags saved for a rom, all 0 if there are none yet.
+    pub fn load(&self, rom_hash: u64) -> io::Result<[u8; FLAG_COUNT]> {
+        let mut flags = [0; FLAG_COUNT];
+        match fs::read(self.path(rom_hash)) {
+            Ok(data) => {
+                let len = data.len().min(FLAG_COUNT);
+                flags[..len].copy_from_slice(&data[..len]);
+                Ok(flags)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(flags),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, rom_hash: u64, flags: &[u8; FLAG_COUNT]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(rom_hash), flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flag_store() {
+        let dir = env::temp_dir().join(format!("chip8-flags-{}", std::process::id()));
+        let store = FlagStore::new(&dir);
+        assert_eq!(store.load(42).unwrap(), [0; FLAG_COUNT]);
+
+        let mut flags = [0; FLAG_COUNT];
+        flags[0] = 12;
+        flags[7] = 34;
+        store.save(42, &flags).unwrap();
+        assert_eq!(store.load(42).unwrap(), flags);
+        assert_eq!(store.load(43).unwrap(), [0; FLAG_COUNT]);
+
+        // the 8 flags of a SCHIP file.
+        fs::write(store.path(44), [1; 8]).unwrap();
+        assert_eq!(store.load(44).unwrap()[..9], [1, 1, 1, 1, 1, 1, 1, 1, 0]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,330 @@
+use std::fs;
+use std::io;
+
+/// The bytes of each glyph of the small font, 4x5 pixels for the 16 hex digits.
+pub const SMALL_GLYPH_LEN: usize = 5;
+/// The bytes of each glyph of the big font, 8x10 pixels for the digits 0 to 9.
+pub const BIG_GLYPH_LEN: usize = 10;
+pub const SMALL_FONT_LEN: usize = 16 * SMALL_GLYPH_LEN;
+pub const BIG_FONT_LEN: usize = 10 * BIG_GLYPH_LEN;
+/// The bytes taken in memory by a font, the big glyphs following the small ones.
+pub const FONT_LEN: usize = SMALL_FONT_LEN + BIG_FONT_LEN;
+
+#[rustfmt::skip]
+const CHIP8: [u8; SMALL_FONT_LEN] = [
+    0xf0, 0x90, 0x90, 0x90, 0xf0, 0x20, 0x60, 0x20, 0x20, 0x70, 0xf0, 0x10, 0xf0, 0x80, 0xf0,
+    0xf0, 0x10, 0xf0, 0x10, 0xf0, 0x90, 0x90, 0xf0, 0x10, 0x10, 0xf0, 0x80, 0xf0, 0x10, 0xf0,
+    0xf0, 0x80, 0xf0, 0x90, 0xf0, 0xf0, 0x10, 0x20, 0x40, 0x40, 0xf0, 0x90, 0xf0, 0x90, 0xf0,
+    0xf0, 0x90, 0xf0, 0x10, 0xf0, 0xf0, 0x90, 0xf0, 0x90, 0x90, 0xe0, 0x90, 0xe0, 0x90, 0xe0,
+    0xf0, 0x80, 0x80, 0x80, 0xf0, 0xe0, 0x90, 0x90, 0x90, 0xe0, 0xf0, 0x80, 0xf0, 0x80, 0xf0,
+    0xf0, 0x80, 0xf0, 0x80, 0x80,
+];
+
+#[rustfmt::skip]
+const VIP: [u8; SMALL_FONT_LEN] = [
+    0xf0, 0x90, 0x90, 0x90, 0xf0, 0x60, 0x20, 0x20, 0x20, 0x70, 0xf0, 0x10, 0xf0, 0x80, 0xf0,
+    0xf0, 0x10, 0xf0, 0x10, 0xf0, 0xa0, 0xa0, 0xf0, 0x20, 0x20, 0xf0, 0x80, 0xf0, 0x10, 0xf0,
+    0xf0, 0x80, 0xf0, 0x90, 0xf0, 0xf0, 0x10, 0x10, 0x10, 0x10, 0xf0, 0x90, 0xf0, 0x90, 0xf0,
+    0xf0, 0x90, 0xf0, 0x10, 0xf0, 0xf0, 0x90, 0xf0, 0x90, 0x90, 0xf0, 0x50, 0x70, 0x50, 0xf0,
+    0xf0, 0x80, 0x80, 0x80, 0xf0, 0xf0, 0x50, 0x50, 0x50, 0xf0, 0xf0, 0x80, 0xf0, 0x80, 0xf0,
+    0xf0, 0x80, 0xf0, 0x80, 0x80,
+];
+
+#[rustfmt::skip]
+const ETI660: [u8; SMALL_FONT_LEN] = [
+    0xe0, 0xa0, 0xa0, 0xa0, 0xe0, 0x20, 0x20, 0x20, 0x20, 0x20, 0xe0, 0x20, 0xe0, 0x80, 0xe0,
+    0xe0, 0x20, 0xe0, 0x20, 0xe0, 0xa0, 0xa0, 0xe0, 0x20, 0x20, 0xe0, 0x80, 0xe0, 0x20, 0xe0,
+    0xe0, 0x80, 0xe0, 0xa0, 0xe0, 0xe0, 0x20, 0x20, 0x20, 0x20, 0xe0, 0xa0, 0xe0, 0xa0, 0xe0,
+    0xe0, 0xa0, 0xe0, 0x20, 0xe0, 0xe0, 0xa0, 0xe0, 0xa0, 0xa0, 0x80, 0x80, 0xe0, 0xa0, 0xe0,
+    0xe0, 0x80, 0x80, 0x80, 0xe0, 0x20, 0x20, 0xe0, 0xa0, 0xe0, 0xe0, 0x80, 0xe0, 0x80, 0xe0,
+    0xe0, 0x80, 0xc0, 0x80, 0x80,
+];
+
+#[rustfmt::skip]
+const DREAM6800: [u8; SMALL_FONT_LEN] = [
+    0xe0, 0xa0, 0xa0, 0xa0, 0xe0, 0x40, 0x40, 0x40, 0x40, 0x40, 0xe0, 0x20, 0xe0, 0x80, 0xe0,
+    0xe0, 0x20, 0xe0, 0x20, 0xe0, 0x80, 0xa0, 0xa0, 0xe0, 0x20, 0xe0, 0x80, 0xe0, 0x20, 0xe0,
+    0xe0, 0x80, 0xe0, 0xa0, 0xe0, 0xe0, 0x20, 0x20, 0x20, 0x20, 0xe0, 0xa0, 0xe0, 0xa0, 0xe0,
+    0xe0, 0xa0, 0xe0, 0x20, 0xe0, 0xe0, 0xa0, 0xe0, 0xa0, 0xa0, 0xc0, 0xa0, 0xe0, 0xa0, 0xc0,
+    0xe0, 0x80, 0x80, 0x80, 0xe0, 0xc0, 0xa0, 0xa0, 0xa0, 0xc0, 0xe0, 0x80, 0xe0, 0x80, 0xe0,
+    0xe0, 0x80, 0xc0, 0x80, 0x80,
+];
+
+#[rustfmt::skip]
+const FISH_N_CHIPS: [u8; SMALL_FONT_LEN] = [
+    0x60, 0xa0, 0xa0, 0xa0, 0xc0, 0x40, 0xc0, 0x40, 0x40, 0xe0, 0xc0, 0x20, 0x40, 0x80, 0xe0,
+    0xc0, 0x20, 0x40, 0x20, 0xc0, 0x20, 0xa0, 0xe0, 0x20, 0x20, 0xe0, 0x80, 0xc0, 0x20, 0xc0,
+    0x40, 0x80, 0xc0, 0xa0, 0x40, 0xe0, 0x20, 0x60, 0x40, 0x40, 0x40, 0xa0, 0x40, 0xa0, 0x40,
+    0x40, 0xa0, 0x60, 0x20, 0x40, 0x40, 0xa0, 0xe0, 0xa0, 0xa0, 0xc0, 0xa0, 0xc0, 0xa0, 0xc0,
+    0x60, 0x80, 0x80, 0x80, 0x60, 0xc0, 0xa0, 0xa0, 0xa0, 0xc0, 0xe0, 0x80, 0xc0, 0x80, 0xe0,
+    0xe0, 0x80, 0xc0, 0x80, 0x80,
+];
+
+/// The big font of SCHIP 1.1.
+#[rustfmt::skip]
+const SCHIP_BIG: [u8; BIG_FONT_LEN] = [
+    0x3c, 0x7e, 0xe7, 0xc3, 0xc3, 0xc3, 0xc3, 0xe7, 0x7e, 0x3c,
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3c,
+    0x3e, 0x7f, 0xc3, 0x06, 0x0c, 0x18, 0x30, 0x60, 0xff, 0xff,
+    0x3c, 0x7e, 0xc3, 0x03, 0x0e, 0x0e, 0x03, 0xc3, 0x7e, 0x3c,
+    0x06, 0x0e, 0x1e, 0x36, 0x66, 0xc6, 0xff, 0xff, 0x06, 0x06,
+    0xff, 0xff, 0xc0, 0xc0, 0xfc, 0xfe, 0x03, 0xc3, 0x7e, 0x3c,
+    0x3e, 0x7c, 0xe0, 0xc0, 0xfc, 0xfe, 0xc3, 0xc3, 0x7e, 0x3c,
+    0xff, 0xff, 0x03, 0x06, 0x0c, 0x18, 0x30, 0x60, 0x60, 0x60,
+    0x3c, 0x7e, 0xc3, 0xc3, 0x7e, 0x7e, 0xc3, 0xc3, 0x7e, 0x3c,
+    0x3c, 0x7e, 0xc3, 0xc3, 0x7f, 0x3f, 0x03, 0x03, 0x3e, 0x7c,
+];
+
+/// The small fonts of the machines that ran CHIP-8, all completed by the big font of SCHIP.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FontSet {
+    /// The common 4x5 font of modern interpreters. Earlier versions of this emulator loaded it
+    /// with the 9 drawn as an 8, and A to F one glyph off.
+    #[default]
+    Chip8,
+    /// The font of the COSMAC VIP interpreter.
+    Vip,
+    Eti660,
+    Dream6800,
+    FishNChips,
+}
+
+impl FontSet {
+    pub fn all() -> [Self; 5] {
+        [
+            FontSet::Chip8,
+            FontSet::Vip,
+            FontSet::Eti660,
+            FontSet::Dream6800,
+            FontSet::FishNChips,
+        ]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FontSet::Chip8 => "chip8",
+            FontSet::Vip => "vip",
+            FontSet::Eti660 => "eti660",
+            FontSet::Dream6800 => "dream6800",
+            FontSet::FishNChips => "fishnchips",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().iter().copied().find(|f| f.name() == name)
+    }
+
+    pub fn small_glyphs(self) -> &'static [u8; SMALL_FONT_LEN] {
+        match self {
+            FontSet::Chip8 => &CHIP8,
+            FontSet::Vip => &VIP,
+            FontSet::Eti660 => &ETI660,
+            FontSet::Dream6800 => &DREAM6800,
+            FontSet::FishNChips => &FISH_N_CHIPS,
+        }
+    }
+}
+
+/// The glyphs loaded in memory for Fx29 and Fx30, at `address`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Font {
+    pub glyphs: [u8; FONT_LEN],
+    pub address: usize,
+}
+
+impl Font {
+    pub fn new(set: FontSet) -> Self {
+        let mut glyphs = [0; FONT_LEN];
+        glyphs[..SMALL_FONT_LEN].copy_from_slice(set.small_glyphs());
+        glyphs[SMALL_FONT_LEN..].copy_from_slice(&SCHIP_BIG);
+        Self { glyphs, address: 0 }
+    }
+
+    /// A user font: the 80 bytes of the small glyphs, optionally followed by the 100 bytes of
+    /// the big ones, which default to those of SCHIP.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() != SMALL_FONT_LEN && data.len() != FONT_LEN {
+            return Err(format!(
+                "a font has {} or {} bytes, not {}",
+                SMALL_FONT_LEN,
+                FONT_LEN,
+                data.len()
+            ));
+        }
+        let mut font = Self::default();
+        font.glyphs[..data.len()].copy_from_slice(data);
+        Ok(font)
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// The built-in set these glyphs are, if any.
+    pub fn set(&self) -> Option<FontSet> {
+        FontSet::all()
+            .iter()
+            .copied()
+            .find(|set| Font::new(*set).glyphs == self.glyphs)
+    }
+
+    /// The address of the small glyph of the hex digit `digit`.
+    pub fn small_glyph(&self, digit: u8) -> usize {
+        self.address + (digit & 0xf) as usize * SMALL_GLYPH_LEN
+    }
+
+    /// The address of the big glyph of `digit`, only 0 to 9 have one.
+    pub fn big_glyph(&self, digit: u8) -> usize {
+        self.address + SMALL_FONT_LEN + (digit & 0xf) as usize * BIG_GLYPH_LEN
+    }
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        Self::new(FontSet::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_font_sets() {
+        for set in FontSet::all().iter() {
+            assert_eq!(FontSet::from_name(set.name()), Some(*set));
+        }
+        assert_eq!(FontSet::from_name("schip"), None);
+
+        let font = Font::new(FontSet::Vip);
+        assert_eq!(font.glyphs[..SMALL_FONT_LEN], VIP);
+        assert_eq!(font.glyphs[SMALL_FONT_LEN..], SCHIP_BIG);
+        assert_eq!(font.set(), Some(FontSet::Vip));
+        assert_eq!(Font::from_bytes(&[0; FONT_LEN]).unwrap().set(), None);
+    }
+
+    // draws the glyphs side by side, a row of pixels per line.
+    fn draw(glyphs: &[u8], width: usize, height: usize) -> Vec<String> {
+        (0..height)
+            .map(|row| {
+                glyphs
+                    .chunks(height)
+                    .map(|glyph| {
+                        (0..width)
+                            .map(|x| ['.', '#'][(glyph[row] >> (7 - x) & 1) as usize])
+                            .collect::<String>()
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_glyphs() {
+        assert_eq!(
+            draw(FontSet::Chip8.small_glyphs(), 4, 5),
+            [
+                "#### ..#. #### #### #..# #### #### #### #### #### #### ###. #### ###. #### ####",
+                "#..# .##. ...# ...# #..# #... #... ...# #..# #..# #..# #..# #... #..# #... #...",
+                "#..# ..#. #### #### #### #### #### ..#. #### #### #### ###. #... #..# #### ####",
+                "#..# ..#. #... ...# ...# ...# #..# .#.. #..# ...# #..# #..# #... #..# #... #...",
+                "#### .### #### #### ...# #### #### .#.. #### #### #..# ###. #### ###. #### #...",
+            ]
+        );
+        assert_eq!(
+            draw(FontSet::Vip.small_glyphs(), 4, 5),
+            [
+                "#### .##. #### #### #.#. #### #### #### #### #### #### #### #### #### #### ####",
+                "#..# ..#. ...# ...# #.#. #... #... ...# #..# #..# #..# .#.# #... .#.# #... #...",
+                "#..# ..#. #### #### #### #### #### ...# #### #### #### .### #... .#.# #### ####",
+                "#..# ..#. #... ...# ..#. ...# #..# ...# #..# ...# #..# .#.# #... .#.# #... #...",
+                "#### .### #### #### ..#. #### #### ...# #### #### #..# #### #### #### #### #...",
+            ]
+        );
+        assert_eq!(
+            draw(FontSet::Eti660.small_glyphs(), 4, 5),
+            [
+                "###. ..#. ###. ###. #.#. ###. ###. ###. ###. ###. ###. #... ###. ..#. ###. ###.",
+                "#.#. ..#. ..#. ..#. #.#. #... #... ..#. #.#. #.#. #.#. #... #... ..#. #... #...",
+                "#.#. ..#. ###. ###. ###. ###. ###. ..#. ###. ###. ###. ###. #... ###. ###. ##..",
+                "#.#. ..#. #... ..#. ..#. ..#. #.#. ..#. #.#. ..#. #.#. #.#. #... #.#. #... #...",
+                "###. ..#. ###. ###. ..#. ###. ###. ..#. ###. ###. #.#. ###. ###. ###. ###. #...",
+            ]
+        );
+        assert_eq!(
+            draw(FontSet::Dream6800.small_glyphs(), 4, 5),
+            [
+                "###. .#.. ###. ###. #... ###. ###. ###. ###. ###. ###. ##.. ###. ##.. ###. ###.",
+                "#.#. .#.. ..#. ..#. #.#. #... #... ..#. #.#. #.#. #.#. #.#. #... #.#. #... #...",
+                "#.#. .#.. ###. ###. #.#. ###. ###. ..#. ###. ###. ###. ###. #... #.#. ###. ##..",
+                "#.#. .#.. #... ..#. ###. ..#. #.#. ..#. #.#. ..#. #.#. #.#. #... #.#. #... #...",
+                "###. .#.. ###. ###. ..#. ###. ###. ..#. ###. ###. #.#. ##.. ###. ##.. ###. #...",
+            ]
+        );
+        assert_eq!(
+            draw(FontSet::FishNChips.small_glyphs(), 4, 5),
+            [
+                ".##. .#.. ##.. ##.. ..#. ###. .#.. ###. .#.. .#.. .#.. ##.. .##. ##.. ###. ###.",
+                "#.#. ##.. ..#. ..#. #.#. #... #... ..#. #.#. #.#. #.#. #.#. #... #.#. #... #...",
+                "#.#. .#.. .#.. .#.. ###. ##.. ##.. .##. .#.. .##. ###. ##.. #... #.#. ##.. ##..",
+                "#.#. .#.. #... ..#. ..#. ..#. #.#. .#.. #.#. ..#. #.#. #.#. #... #.#. #... #...",
+                "##.. ###. ###. ##.. ..#. ##.. .#.. .#.. .#.. .#.. #.#. ##.. .##. ##.. ###. #...",
+            ]
+        );
+        assert_eq!(
+            draw(&SCHIP_BIG[..50], 8, 10),
+            [
+                "..####.. ...##... ..#####. ..####.. .....##.",
+                ".######. ..###... .####### .######. ....###.",
+                "###..### .#.##... ##....## ##....## ...####.",
+                "##....## ...##... .....##. ......## ..##.##.",
+                "##....## ...##... ....##.. ....###. .##..##.",
+                "##....## ...##... ...##... ....###. ##...##.",
+                "##....## ...##... ..##.... ......## ########",
+                "###..### ...##... .##..... ##....## ########",
+                ".######. ...##... ######## .######. .....##.",
+                "..####.. ..####.. ######## ..####.. .....##.",
+            ]
+        );
+        assert_eq!(
+            draw(&SCHIP_BIG[50..], 8, 10),
+            [
+                "######## ..#####. ######## ..####.. ..####..",
+                "######## .#####.. ######## .######. .######.",
+                "##...... ###..... ......## ##....## ##....##",
+                "##...... ##...... .....##. ##....## ##....##",
+                "######.. ######.. ....##.. .######. .#######",
+                "#######. #######. ...##... .######. ..######",
+                "......## ##....## ..##.... ##....## ......##",
+                "##....## ##....## .##..... ##....## ......##",
+                ".######. .######. .##..... .######. ..#####.",
+                "..####.. ..####.. .##..... ..####.. .#####..",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_user_font() {
+        let font = Font::from_bytes(&ETI660).unwrap();
+        assert_eq!(font, Font::new(FontSet::Eti660));
+
+        let data = [0x55; FONT_LEN];
+        assert_eq!(Font::from_bytes(&data).unwrap().glyphs, data);
+        assert!(Font::from_bytes(&data[..100]).is_err());
+    }
+
+    #[test]
+    fn test_glyph_addresses() {
+        let font = Font {
+            address: 0x50,
+            ..Font::default()
+        };
+        assert_eq!(font.small_glyph(0), 0x50);
+        assert_eq!(font.small_glyph(0xf), 0x50 + 75);
+        assert_eq!(font.small_glyph(0x1a), font.small_glyph(0xa));
+        assert_eq!(font.big_glyph(0), 0x50 + 80);
+        assert_eq!(font.big_glyph(9), 0x50 + 170);
+    }
+}
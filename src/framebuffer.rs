@@ -0,0 +1,222 @@
+use crate::config::*;
+
+/// A rectangle of pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    /// The whole of a `width` x `height` screen.
+    pub fn full(width: usize, height: usize) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+
+    /// The smallest rectangle containing both.
+    pub fn union(self, other: Rect) -> Self {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Self {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+}
+
+/// The monochrome screen, 64 pixels wide and one bit per pixel: each row is a `u64` with the
+/// leftmost pixel in the most significant bit. The region changed since it was last taken is
+/// tracked, so that only that part needs to be redrawn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    rows: Vec<u64>,
+    dirty: Option<Rect>,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self::with_height(DISPLAY_HEIGHT)
+    }
+
+    /// A blank screen, all dirty since it was never drawn.
+    pub fn with_height(height: usize) -> Self {
+        Self {
+            rows: vec![0; height],
+            dirty: Some(Rect::full(DISPLAY_WIDTH, height)),
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn rows(&self) -> &[u64] {
+        &self.rows
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.rows[y] & (1 << (DISPLAY_WIDTH - 1 - x)) != 0
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, lit: bool) {
+        let bit = 1 << (DISPLAY_WIDTH - 1 - x);
+        if lit {
+            self.rows[y] |= bit;
+        } else {
+            self.rows[y] &= !bit;
+        }
+        self.mark_dirty(Rect {
+            x,
+            y,
+            width: 1,
+            height: 1,
+        });
+    }
+
+    /// Replaces a whole row, the leftmost pixel in the most significant bit.
+    pub fn set_row(&mut self, y: usize, bits: u64) {
+        if self.rows[y] != bits {
+            self.rows[y] = bits;
+            self.mark_dirty(Rect {
+                x: 0,
+                y,
+                width: DISPLAY_WIDTH,
+                height: 1,
+            });
+        }
+    }
+
+    /// Writes the pixels of `rect` as colors to the buffer, which covers the whole screen.
+    pub fn write_region(&self, buffer: &mut [(u8, u8, u8)], rect: Rect) {
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                buffer[y * DISPLAY_WIDTH + x] = if self.get(x, y) {
+                    (0xff, 0xff, 0xff)
+                } else {
+                    (0, 0, 0)
+                };
+            }
+        }
+    }
+
+    /// The pixels row by row, from the top left.
+    pub fn pixels(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.height()).flat_map(move |y| (0..DISPLAY_WIDTH).map(move |x| self.get(x, y)))
+    }
+
+    pub fn clear(&mut self) {
+        self.rows.iter_mut().for_each(|row| *row = 0);
+        self.mark_dirty(Rect::full(DISPLAY_WIDTH, self.height()));
+    }
+
+    /// XORs the 8 pixels of a sprite row at (x, y), wrapping around the right edge. Returns
+    /// whether a lit pixel was turned off.
+    pub fn xor_sprite_row(&mut self, x: usize, y: usize, byte: u8) -> bool {
+        if byte == 0 {
+            return false;
+        }
+        let bits = ((byte as u64) << (DISPLAY_WIDTH - 8)).rotate_right(x as u32);
+        let collision = self.rows[y] & bits != 0;
+        self.rows[y] ^= bits;
+        let (x, width) = if x + 8 <= DISPLAY_WIDTH {
+            (x, 8)
+        } else {
+            (0, DISPLAY_WIDTH)
+        };
+        self.mark_dirty(Rect {
+            x,
+            y,
+            width,
+            height: 1,
+        });
+        collision
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.is_some()
+    }
+
+    /// Returns the region changed since the last call, if any.
+    pub fn take_dirty(&mut self) -> Option<Rect> {
+        self.dirty.take()
+    }
+
+    /// Marks a region as changed, when its colours change.
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(rect),
+            None => rect,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xor_sprite_row() {
+        let mut fb = Framebuffer::new();
+        fb.take_dirty();
+        assert!(!fb.xor_sprite_row(4, 2, 0b1100_0001));
+        assert!(fb.get(4, 2) && fb.get(5, 2) && fb.get(11, 2));
+        assert!(!fb.get(6, 2));
+        assert_eq!(
+            fb.take_dirty(),
+            Some(Rect {
+                x: 4,
+                y: 2,
+                width: 8,
+                height: 1
+            })
+        );
+        assert_eq!(fb.take_dirty(), None);
+
+        // wraps around, and collides
+        assert!(fb.xor_sprite_row(61, 2, 0b0000_0001));
+        assert!(!fb.get(4, 2));
+        assert!(!fb.xor_sprite_row(62, 3, 0xff));
+        assert!(fb.get(63, 3) && fb.get(5, 3));
+        assert_eq!(
+            fb.take_dirty().map(|r| (r.x, r.width)),
+            Some((0, DISPLAY_WIDTH))
+        );
+    }
+
+    #[test]
+    fn test_dirty_union() {
+        let mut fb = Framebuffer::new();
+        fb.take_dirty();
+        fb.set(1, 1, true);
+        fb.set(10, 20, true);
+        assert_eq!(
+            fb.take_dirty(),
+            Some(Rect {
+                x: 1,
+                y: 1,
+                width: 10,
+                height: 20
+            })
+        );
+        fb.clear();
+        assert_eq!(fb.take_dirty(), Some(Rect::full(64, 32)));
+        assert!(fb.pixels().all(|p| !p));
+    }
+}
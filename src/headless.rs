@@ -0,0 +1,188 @@
+use crate::config::*;
+use crate::cpu::Processor;
+use crate::hash::fnv1a;
+use crate::movie::KeyEvent;
+
+/// Runs a processor frame by frame without display nor audio, with scripted keypad input.
+pub struct Headless<T: Processor> {
+    pub cpu: T,
+    ipf: u32,
+    frames: u64,
+    input: Vec<KeyEvent>,
+    next_event: usize,
+}
+
+impl<T: Processor> Headless<T> {
+    pub fn new(cpu: T, ipf: u32) -> Self {
+        Self {
+            cpu,
+            ipf,
+            frames: 0,
+            input: Vec::new(),
+            next_event: 0,
+        }
+    }
+
+    /// Sets the keypad input, which must be sorted by frame.
+    pub fn with_input(mut self, input: Vec<KeyEvent>) -> Self {
+        self.input = input;
+        self.next_event = 0;
+        self
+    }
+
+    /// Runs `frames` frames and returns the number of instructions that were executed.
+    pub fn run_frames(&mut self, frames: u64) -> u64 {
+        let mut ticks = 0;
+        for _ in 0..frames {
+            while let Some(event) = self
+                .input
+                .get(self.next_event)
+                .filter(|e| e.frame <= self.frames)
+            {
+                self.cpu.set_key_press(event.key, event.down);
+                self.next_event += 1;
+            }
+            ticks += self.cpu.run_frame(self.ipf).ticks as u64;
+            self.frames += 1;
+        }
+        ticks
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn screen(&self) -> Screen {
+        let (width, height) = self.cpu.display_size();
+        let mut buffer = vec![BG_COLOR; width * height];
+        self.cpu.get_vram_buffer(&mut buffer);
+        Screen {
+            width,
+            height,
+            pixels: buffer.iter().map(|p| *p != BG_COLOR).collect(),
+        }
+    }
+}
+
+/// Which pixels of the screen are lit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Screen {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<bool>,
+}
+
+impl Screen {
+    pub fn hash(&self) -> u64 {
+        let bytes: Vec<u8> = self.pixels.iter().map(|p| *p as u8).collect();
+        fnv1a(&bytes)
+    }
+
+    /// One line per row, `#` for the lit pixels and `.` for the others.
+    pub fn to_text(&self) -> String {
+        self.pixels
+            .chunks(self.width)
+            .map(|row| {
+                let mut line: String = row.iter().map(|p| if *p { '#' } else { '.' }).collect();
+                line.push('\n');
+                line
+            })
+            .collect()
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let rows: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+        let width = rows.first().map_or(0, |r| r.len());
+        if rows.iter().any(|r| r.len() != width) {
+            return Err(String::from("rows have different widths"));
+        }
+        let pixels = rows
+            .iter()
+            .flat_map(|r| r.chars())
+            .map(|c| match c {
+                '#' => Ok(true),
+                '.' => Ok(false),
+                _ => Err(format!("invalid pixel: {:?}", c)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            width,
+            height: rows.len(),
+            pixels,
+        })
+    }
+
+    /// Compares with the expected screen, returns `None` if they are the same, or a picture of
+    /// the differences: `+` for the pixels that are lit but shouldn't be, `-` for those that
+    /// should be lit but aren't.
+    pub fn diff(&self, expected: &Screen) -> Option<String> {
+        if self == expected {
+            return None;
+        }
+        if (self.width, self.height) != (expected.width, expected.height) {
+            return Some(format!(
+                "the screen is {}x{}, expected {}x{}",
+                self.width, self.height, expected.width, expected.height
+            ));
+        }
+        let mut picture = String::new();
+        let mut count = 0;
+        for (n, (actual, expected)) in self.pixels.iter().zip(expected.pixels.iter()).enumerate() {
+            picture.push(match (actual, expected) {
+                (true, true) => '#',
+                (false, false) => '.',
+                (true, false) => '+',
+                (false, true) => '-',
+            });
+            if actual != expected {
+                count += 1;
+            }
+            if n % self.width == self.width - 1 {
+                picture.push('\n');
+            }
+        }
+        Some(format!("{} pixels differ:\n{}", count, picture))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+
+    #[test]
+    fn test_headless_input() {
+        let mut cpu = CPU::new();
+        // 0x200: LD V0, K; 0x202: LD F, V0; 0x204: DRW V1, V1, 5; 0x206: JMP 0x206
+        cpu.load_rom(&[0xf0, 0x0a, 0xf0, 0x29, 0xd1, 0x15, 0x12, 0x06]);
+        let input = vec![KeyEvent {
+            frame: 5,
+            key: 7,
+            down: true,
+        }];
+        let mut headless = Headless::new(cpu, 10).with_input(input);
+        headless.run_frames(5);
+        assert_eq!(headless.cpu.pc, 0x200);
+        headless.run_frames(1);
+        assert_eq!(headless.cpu.v[0], 7);
+        assert_eq!(headless.frame_count(), 6);
+
+        let screen = headless.screen();
+        let text = screen.to_text();
+        // the top row of the 7 glyph
+        assert!(text.starts_with("####...."));
+        assert_eq!(Screen::from_text(&text).unwrap(), screen);
+    }
+
+    #[test]
+    fn test_screen_diff() {
+        let expected = Screen::from_text("#.\n..\n").unwrap();
+        let actual = Screen::from_text("..\n.#\n").unwrap();
+        assert_eq!(expected.diff(&expected), None);
+        assert_eq!(
+            actual.diff(&expected),
+            Some(String::from("2 pixels differ:\n-.\n.+\n"))
+        );
+        assert_ne!(actual.hash(), expected.hash());
+    }
+}
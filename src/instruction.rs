@@ -0,0 +1,229 @@
+use crate::platform::Platform;
+
+/// A decoded instruction, named after its mnemonic. `x` and `y` are register numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    Cls,
+    Ret,
+    Jp(usize),
+    Call(usize),
+    SeImm(usize, u8),
+    SneImm(usize, u8),
+    Se(usize, usize),
+    LdImm(usize, u8),
+    AddImm(usize, u8),
+    Ld(usize, usize),
+    Or(usize, usize),
+    And(usize, usize),
+    Xor(usize, usize),
+    Add(usize, usize),
+    Sub(usize, usize),
+    Shr(usize, usize),
+    Subn(usize, usize),
+    Shl(usize, usize),
+    Sne(usize, usize),
+    LdI(usize),
+    JpV0(usize),
+    Rnd(usize, u8),
+    Drw(usize, usize, u8),
+    Skp(usize),
+    Sknp(usize),
+    LdVxDt(usize),
+    LdVxK(usize),
+    LdDtVx(usize),
+    LdStVx(usize),
+    AddI(usize),
+    LdF(usize),
+    /// SCHIP FX30: points I to the big glyph of the digit in Vx.
+    LdHf(usize),
+    LdB(usize),
+    LdMemVx(usize),
+    LdVxMem(usize),
+    /// SCHIP FX75: stores V0 to Vx in the RPL user flags.
+    LdFlagsVx(usize),
+    /// SCHIP FX85: loads V0 to Vx from the RPL user flags.
+    LdVxFlags(usize),
+    /// CHIP-8X 02A0: cycles the background colour.
+    BgColor,
+    /// CHIP-8X 5XY1: adds the colours in Vy to Vx, each 3 bit field separately.
+    AddColors(usize, usize),
+    /// CHIP-8X BXYN: sets the foreground colour Vy of the zones at Vx, Vx+1.
+    Color(usize, usize, u8),
+    /// CHIP-8X EXF2: skips if the key Vx of the second keypad is pressed.
+    SkpPad2(usize),
+    /// CHIP-8X EXF5: skips if the key Vx of the second keypad is not pressed.
+    SknpPad2(usize),
+    /// CHIP-8X FXF8: writes Vx to the output port.
+    Out(usize),
+    /// CHIP-8X FXFB: reads the input port into Vx.
+    In(usize),
+    /// MegaChip 0010: switches back to the monochrome screen.
+    MegaOff,
+    /// MegaChip 0011: switches to the colour screen.
+    MegaOn,
+    /// MegaChip 01NN NNNN: loads the 24 bit address in I, the low 16 bits are the next word.
+    LdIFar(u8),
+    /// MegaChip 02NN: loads NN colours of the palette from I.
+    LdPalette(u8),
+    /// MegaChip 03NN: sets the width of the sprites, 0 for 256.
+    SpriteWidth(u8),
+    /// MegaChip 04NN: sets the height of the sprites, 0 for 256.
+    SpriteHeight(u8),
+    /// MegaChip 05NN: sets the opacity of the screen.
+    ScreenAlpha(u8),
+    /// MegaChip 060N: plays the digitised sound at I, looping when N is 0.
+    PlaySample(bool),
+    /// MegaChip 0700: stops the digitised sound.
+    StopSample,
+    /// MegaChip 080N: sets how the sprites are blended.
+    BlendMode(u8),
+    /// MegaChip 09NN: sets the colour the sprites collide with.
+    CollisionColor(u8),
+    /// Not a CHIP-8 instruction, it is skipped.
+    Unknown,
+}
+
+impl Instruction {
+    pub fn decode(inst: u16) -> Self {
+        let nibs = (
+            (inst & 0xf000) >> 12,
+            (inst & 0x0f00) >> 8,
+            (inst & 0x00f0) >> 4,
+            (inst & 0x000f),
+        );
+
+        let nnn = (inst & 0x0fff) as usize;
+        let nn = (inst & 0x00ff) as u8;
+        let n = nibs.3 as u8;
+        let x = nibs.1 as usize;
+        let y = nibs.2 as usize;
+
+        match nibs {
+            (0x00, 0x00, 0x0e, 0x0e) => Self::Ret,
+            (0x00, 0x00, 0x0e, 0x00) => Self::Cls,
+            (0x01, _, _, _) => Self::Jp(nnn),
+            (0x02, _, _, _) => Self::Call(nnn),
+            (0x03, _, _, _) => Self::SeImm(x, nn),
+            (0x04, _, _, _) => Self::SneImm(x, nn),
+            (0x05, _, _, 0x00) => Self::Se(x, y),
+            (0x06, _, _, _) => Self::LdImm(x, nn),
+            (0x07, _, _, _) => Self::AddImm(x, nn),
+            (0x08, _, _, 0x00) => Self::Ld(x, y),
+            (0x08, _, _, 0x01) => Self::Or(x, y),
+            (0x08, _, _, 0x02) => Self::And(x, y),
+            (0x08, _, _, 0x03) => Self::Xor(x, y),
+            (0x08, _, _, 0x04) => Self::Add(x, y),
+            (0x08, _, _, 0x05) => Self::Sub(x, y),
+            (0x08, _, _, 0x06) => Self::Shr(x, y),
+            (0x08, _, _, 0x07) => Self::Subn(x, y),
+            (0x08, _, _, 0x0e) => Self::Shl(x, y),
+            (0x09, _, _, 0x00) => Self::Sne(x, y),
+            (0x0a, _, _, _) => Self::LdI(nnn),
+            (0x0b, _, _, _) => Self::JpV0(nnn),
+            (0x0c, _, _, _) => Self::Rnd(x, nn),
+            (0x0d, _, _, _) => Self::Drw(x, y, n),
+            (0x0e, _, 0x09, 0x0e) => Self::Skp(x),
+            (0x0e, _, 0x0a, 0x01) => Self::Sknp(x),
+            (0x0f, _, 0x00, 0x07) => Self::LdVxDt(x),
+            (0x0f, _, 0x00, 0x0a) => Self::LdVxK(x),
+            (0x0f, _, 0x01, 0x05) => Self::LdDtVx(x),
+            (0x0f, _, 0x01, 0x08) => Self::LdStVx(x),
+            (0x0f, _, 0x01, 0x0e) => Self::AddI(x),
+            (0x0f, _, 0x02, 0x09) => Self::LdF(x),
+            (0x0f, _, 0x03, 0x00) => Self::LdHf(x),
+            (0x0f, _, 0x03, 0x03) => Self::LdB(x),
+            (0x0f, _, 0x05, 0x05) => Self::LdMemVx(x),
+            (0x0f, _, 0x06, 0x05) => Self::LdVxMem(x),
+            (0x0f, _, 0x07, 0x05) => Self::LdFlagsVx(x),
+            (0x0f, _, 0x08, 0x05) => Self::LdVxFlags(x),
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Decodes an instruction of `platform`, whose extra instructions replace the machine
+    /// language calls and the unused opcodes of CHIP-8.
+    pub fn decode_for(inst: u16, platform: Platform) -> Self {
+        let x = (inst as usize & 0x0f00) >> 8;
+        let y = (inst as usize & 0x00f0) >> 4;
+        match (platform, inst & 0xf000, inst & 0x00ff) {
+            // the routine clearing the two pages of the hi-res screen.
+            (Platform::HiRes, _, _) if inst == 0x0230 => Self::Cls,
+            (Platform::Chip8X, _, _) if inst == 0x02a0 => Self::BgColor,
+            (Platform::Chip8X, 0x5000, _) if inst & 0xf == 1 => Self::AddColors(x, y),
+            (Platform::Chip8X, 0xb000, _) => Self::Color(x, y, inst as u8 & 0xf),
+            (Platform::Chip8X, 0xe000, 0xf2) => Self::SkpPad2(x),
+            (Platform::Chip8X, 0xe000, 0xf5) => Self::SknpPad2(x),
+            (Platform::Chip8X, 0xf000, 0xf8) => Self::Out(x),
+            (Platform::Chip8X, 0xf000, 0xfb) => Self::In(x),
+            (Platform::MegaChip, 0x0000, _) => Self::decode_megachip(inst),
+            _ => Self::decode(inst),
+        }
+    }
+
+    fn decode_megachip(inst: u16) -> Self {
+        let nn = inst as u8;
+        match (inst >> 8, nn) {
+            (0x00, 0x10) => Self::MegaOff,
+            (0x00, 0x11) => Self::MegaOn,
+            (0x01, _) => Self::LdIFar(nn),
+            (0x02, _) => Self::LdPalette(nn),
+            (0x03, _) => Self::SpriteWidth(nn),
+            (0x04, _) => Self::SpriteHeight(nn),
+            (0x05, _) => Self::ScreenAlpha(nn),
+            (0x06, _) => Self::PlaySample(nn & 0xf == 0),
+            (0x07, 0x00) => Self::StopSample,
+            (0x08, _) => Self::BlendMode(nn & 0xf),
+            (0x09, _) => Self::CollisionColor(nn),
+            _ => Self::decode(inst),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(Instruction::decode(0x00e0), Instruction::Cls);
+        assert_eq!(Instruction::decode(0x1abc), Instruction::Jp(0xabc));
+        assert_eq!(Instruction::decode(0x8ab6), Instruction::Shr(0xa, 0xb));
+        assert_eq!(Instruction::decode(0xd12f), Instruction::Drw(1, 2, 0xf));
+        assert_eq!(Instruction::decode(0xf365), Instruction::LdVxMem(3));
+        assert_eq!(Instruction::decode(0xf230), Instruction::LdHf(2));
+        assert_eq!(Instruction::decode(0xf775), Instruction::LdFlagsVx(7));
+        assert_eq!(Instruction::decode(0xf185), Instruction::LdVxFlags(1));
+        assert_eq!(Instruction::decode(0x5121), Instruction::Unknown);
+        assert_eq!(Instruction::decode(0xe1ff), Instruction::Unknown);
+    }
+
+    #[test]
+    fn test_decode_for() {
+        let decode = Instruction::decode_for;
+        assert_eq!(decode(0x0230, Platform::HiRes), Instruction::Cls);
+        assert_eq!(decode(0x0230, Platform::Chip8), Instruction::Unknown);
+        assert_eq!(
+            decode(0x5121, Platform::Chip8X),
+            Instruction::AddColors(1, 2)
+        );
+        assert_eq!(
+            decode(0xb124, Platform::Chip8X),
+            Instruction::Color(1, 2, 4)
+        );
+        assert_eq!(decode(0xb124, Platform::Chip8), Instruction::JpV0(0x124));
+        assert_eq!(decode(0xe3f2, Platform::Chip8X), Instruction::SkpPad2(3));
+        assert_eq!(decode(0xf4fb, Platform::Chip8X), Instruction::In(4));
+        assert_eq!(decode(0x1260, Platform::Eti660), Instruction::Jp(0x260));
+        assert_eq!(decode(0x0011, Platform::MegaChip), Instruction::MegaOn);
+        assert_eq!(
+            decode(0x0123, Platform::MegaChip),
+            Instruction::LdIFar(0x23)
+        );
+        assert_eq!(
+            decode(0x0601, Platform::MegaChip),
+            Instruction::PlaySample(false)
+        );
+        assert_eq!(decode(0x00e0, Platform::MegaChip), Instruction::Cls);
+        assert_eq!(decode(0x0011, Platform::Chip8), Instruction::Unknown);
+    }
+}
@@ -0,0 +1,26 @@
+pub mod audio;
+pub mod cdp1802;
+pub mod chip8;
+pub mod config;
+pub mod cpu;
+pub mod debugger;
+pub mod emulator;
+pub mod filter;
+pub mod flags;
+pub mod font;
+pub mod framebuffer;
+pub mod hash;
+pub mod headless;
+pub mod instruction;
+pub mod megachip;
+pub mod movie;
+pub mod overlay;
+pub mod platform;
+pub mod quirks;
+#[cfg(feature = "recompiler")]
+pub mod recompiler;
+pub mod rng;
+pub mod speed;
+pub mod timing;
+pub mod vip;
+pub mod wav;
@@ -1,10 +1,15 @@
-pub mod chip8;
-pub mod config;
-pub mod cpu;
-pub mod debugger;
-
-use clap::{App, Arg};
-use config::*;
+use chip_8::config::*;
+use chip_8::cpu::Processor;
+use chip_8::flags::FlagStore;
+use chip_8::font::{Font, FontSet};
+use chip_8::headless::Headless;
+use chip_8::platform::Platform;
+#[cfg(feature = "recompiler")]
+use chip_8::recompiler;
+use chip_8::{audio, chip8, cpu, debugger, filter, movie, quirks, rng, speed, timing, vip};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::fs;
+use std::time::Instant;
 use v_display::display::DisplayBuilder;
 
 fn main() {
@@ -12,6 +17,7 @@ fn main() {
         .version("0.1")
         .author("mpostma")
         .about("A basic chip-8 emulator")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("ROM")
                 .help("path to the rom to emulate")
@@ -26,44 +32,449 @@ fn main() {
                 .help("print debug info"),
         )
         .arg(
-            Arg::with_name("clock_time")
-                .short("c")
-                .long("clock")
+            Arg::with_name("ipf")
+                .short("i")
+                .long("ipf")
+                .takes_value(true)
+                .validator(positive)
+                .help("set the number of instructions executed per 60Hz frame (default to 10)"),
+        )
+        .arg(
+            Arg::with_name("display_wait")
+                .long("display-wait")
+                .takes_value(false)
+                .help("make sprite drawing wait for the vertical blank, like the COSMAC VIP"),
+        )
+        .arg(
+            Arg::with_name("timing")
+                .long("timing")
+                .takes_value(true)
+                .possible_values(&["ipf", "vip"])
+                .help(
+                    "set how long the instructions take: ipf runs the same number of them on each \
+                     frame, vip follows the cycle counts of the COSMAC VIP (default to ipf)",
+                ),
+        )
+        .arg(
+            Arg::with_name("platform")
+                .long("platform")
+                .takes_value(true)
+                .possible_values(&["chip8", "hires", "eti660", "chip8x", "megachip"])
+                .conflicts_with("vip")
+                .help(
+                    "set the variant of CHIP-8: hires shows 64x64, eti660 loads the programs at \
+                     0x600 and shows 64x48, chip8x has the colour instructions, megachip switches \
+                     to a 256x192 colour screen (default to chip8)",
+                ),
+        )
+        .arg(
+            Arg::with_name("flags_dir")
+                .long("flags-dir")
+                .takes_value(true)
+                .help(
+                    "set the directory where the SCHIP user flags of each rom are saved \
+                     (default to ~/.chip8/flags)",
+                ),
+        )
+        .arg(
+            Arg::with_name("font")
+                .long("font")
+                .takes_value(true)
+                .possible_values(&["chip8", "vip", "eti660", "dream6800", "fishnchips"])
+                .conflicts_with("vip")
+                .help("set the small font, the big one is always that of SCHIP (default to chip8)"),
+        )
+        .arg(
+            Arg::with_name("font_file")
+                .long("font-file")
+                .takes_value(true)
+                .conflicts_with_all(&["font", "vip"])
+                .help(
+                    "load the font from a file of 80 bytes for the small glyphs, optionally \
+                     followed by 100 bytes for the big ones",
+                ),
+        )
+        .arg(
+            Arg::with_name("font_address")
+                .long("font-address")
+                .takes_value(true)
+                .conflicts_with("vip")
+                .help("set the address of the font in hexadecimal (default to 0)"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
                 .takes_value(true)
-                .help("set clock time value in ms (default to 2ms)"),
+                .help("set the seed of the random number generator (default to a random seed)"),
+        )
+        .arg(
+            Arg::with_name("rng")
+                .long("rng")
+                .takes_value(true)
+                .possible_values(&["xorshift", "lcg", "vip"])
+                .help(
+                    "set the random number generator model, vip reads the interpreter given \
+                     with --rng-interpreter (default to xorshift)",
+                ),
+        )
+        .arg(
+            Arg::with_name("rng_interpreter")
+                .long("rng-interpreter")
+                .takes_value(true)
+                .value_name("INTERPRETER")
+                .help("the COSMAC VIP CHIP-8 interpreter image, whose code the vip generator reads"),
+        )
+        .arg(
+            Arg::with_name("filter")
+                .short("f")
+                .long("filter")
+                .takes_value(true)
+                .possible_values(&["none", "blend", "or"])
+                .help(
+                    "set the anti-flicker filter: none redraws on frame boundaries only, blend \
+                     fades pixels out, or shows the last two frames (default to none)",
+                ),
+        )
+        .arg(
+            Arg::with_name("decay")
+                .long("decay")
+                .takes_value(true)
+                .help("set the brightness kept by fading pixels on each frame with the blend filter, between 0 and 1 (default to 0.5)"),
+        )
+        .arg(
+            Arg::with_name("tone")
+                .long("tone")
+                .takes_value(true)
+                .help("set the frequency of the beep in Hz, above 0 (default to 440)"),
+        )
+        .arg(
+            Arg::with_name("volume")
+                .long("volume")
+                .takes_value(true)
+                .help("set the volume of the beep, between 0 and 1 (default to 0.25)"),
+        )
+        .arg(
+            Arg::with_name("waveform")
+                .long("waveform")
+                .takes_value(true)
+                .possible_values(&["square", "sine", "triangle"])
+                .help("set the waveform of the beep (default to square)"),
+        )
+        .arg(
+            Arg::with_name("audio_sync")
+                .long("audio-sync")
+                .takes_value(false)
+                .help("pace the emulation on the audio clock instead of the system timer"),
+        )
+        .arg(
+            Arg::with_name("wav")
+                .long("wav")
+                .takes_value(true)
+                .help("record the sound to a WAV file"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .takes_value(true)
+                .conflicts_with("play")
+                .help("record the keypad input to a movie file"),
+        )
+        .arg(
+            Arg::with_name("play")
+                .long("play")
+                .takes_value(true)
+                .help("play back the keypad input of a movie file, with its settings"),
+        )
+        .arg(
+            Arg::with_name("decode_cache")
+                .long("decode-cache")
+                .takes_value(false)
+                .help("cache the decoded instructions by address (see cargo bench)"),
+        )
+        .arg(
+            Arg::with_name("recompiler")
+                .long("recompiler")
+                .takes_value(false)
+                .help("run basic blocks with the recompiler (needs the recompiler feature)"),
+        )
+        .arg(
+            Arg::with_name("vip")
+                .long("vip")
+                .takes_value(true)
+                .value_name("INTERPRETER")
+                .help("run the rom on an emulated COSMAC VIP, with the original CHIP-8 interpreter image"),
+        )
+        .arg(
+            Arg::with_name("monitor")
+                .long("monitor")
+                .takes_value(true)
+                .requires("vip")
+                .help("load the VIP monitor ROM, where the interpreter finds its font"),
+        )
+        .arg(
+            Arg::with_name("speed")
+                .short("s")
+                .long("speed")
+                .takes_value(true)
+                .help(
+                    "set the speed multiplier, above 0, or \"max\" to run unthrottled \
+                     (default to 1)",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("run a rom unthrottled without display, and report the instructions per second")
+                .arg(
+                    Arg::with_name("ROM")
+                        .help("path to the rom to run")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("frames")
+                        .short("n")
+                        .long("frames")
+                        .takes_value(true)
+                        .validator(positive)
+                        .help("set the number of frames to run (default to 6000)"),
+                )
+                .arg(
+                    Arg::with_name("ipf")
+                        .short("i")
+                        .long("ipf")
+                        .takes_value(true)
+                        .validator(positive)
+                        .help("set the number of instructions per frame (default to 1000)"),
+                )
+                .arg(
+                    Arg::with_name("decode_cache")
+                        .long("decode-cache")
+                        .takes_value(false)
+                        .help("cache the decoded instructions"),
+                )
+                .arg(
+                    Arg::with_name("recompiler")
+                        .long("recompiler")
+                        .takes_value(false)
+                        .help("run basic blocks with the recompiler"),
+                ),
         )
         .get_matches();
+    check_recompiler(&matches);
+    if let Some(matches) = matches.subcommand_matches("bench") {
+        check_recompiler(matches);
+        bench(matches);
+        return;
+    }
     //safe to unwrap here because ROM is required.
     let filename = matches.value_of("ROM").unwrap();
 
+    // a movie is replayed with the settings it was recorded with.
+    let movie = match matches.value_of("play") {
+        Some(path) => movie::Movie::load(path).expect("could not load the movie"),
+        None => movie::Movie {
+            rom_hash: 0,
+            seed: matches.value_of("seed").map_or_else(rand::random, |s| {
+                s.parse::<u64>().expect("invalid seed value")
+            }),
+            rng: matches
+                .value_of("rng")
+                .map_or(rng::RngModel::Xorshift, |name| {
+                    movie::rng_model(name).unwrap()
+                }),
+            ipf: matches.value_of("ipf").map_or(DEFAULT_IPF, |ipf| {
+                ipf.parse::<u32>().expect("invalid ipf value")
+            }),
+            quirks: quirks::Quirks {
+                display_wait: matches.is_present("display_wait"),
+            },
+            timing: matches
+                .value_of("timing")
+                .map_or(timing::Timing::Ipf, |name| movie::timing(name).unwrap()),
+            platform: matches
+                .value_of("platform")
+                .map_or(Platform::Chip8, |name| Platform::from_name(name).unwrap()),
+            font: font(&matches),
+            events: Vec::new(),
+        },
+    };
+    let mut cpu = cpu::CPU::with_quirks(movie.quirks);
+    cpu.rng = match movie.rng {
+        rng::RngModel::Vip => {
+            let path = matches
+                .value_of("rng_interpreter")
+                .expect("the vip generator needs --rng-interpreter");
+            let interpreter = fs::read(path).expect("could not read the interpreter");
+            rng::Rng::vip(movie.seed, &interpreter)
+        }
+        model => rng::Rng::new(model, movie.seed),
+    };
+    cpu.timing = movie.timing;
+    cpu.set_platform(movie.platform);
+    cpu.set_font(movie.font.clone())
+        .expect("invalid font address");
+    cpu.set_decode_cache(matches.is_present("decode_cache"));
+    let decay = matches.value_of("decay").map_or(0.5, |d| {
+        d.parse::<f32>()
+            .ok()
+            .filter(|d| (0.0..=1.0).contains(d))
+            .expect("invalid decay value")
+    });
+    let filter = match matches.value_of("filter") {
+        Some("blend") => filter::Filter::Blend(decay),
+        Some("or") => filter::Filter::Or,
+        _ => filter::Filter::None,
+    };
+    let default_tone = audio::Tone::default();
+    let tone = audio::Tone {
+        frequency: matches
+            .value_of("tone")
+            .map_or(default_tone.frequency, |f| {
+                f.parse::<f32>()
+                    .ok()
+                    .filter(|f| f.is_finite() && *f > 0.0)
+                    .expect("invalid tone value")
+            }),
+        volume: matches.value_of("volume").map_or(default_tone.volume, |v| {
+            v.parse::<f32>()
+                .ok()
+                .filter(|v| (0.0..=1.0).contains(v))
+                .expect("invalid volume value")
+        }),
+        waveform: match matches.value_of("waveform") {
+            Some("sine") => audio::Waveform::Sine,
+            Some("triangle") => audio::Waveform::Triangle,
+            _ => audio::Waveform::Square,
+        },
+    };
+    let speed = matches
+        .value_of("speed")
+        .map_or(speed::Speed::normal(), |s| {
+            speed::Speed::parse(s).expect("invalid speed value")
+        });
+
+    // the window keeps its height, taller screens get smaller pixels.
+    let (width, height) = movie.platform.display_size();
     let display = DisplayBuilder::new(
         &filename,
-        DISPLAY_WIDTH as u32,
-        DISPLAY_HEIGHT as u32,
-        PIX_SIZE as u32,
+        width as u32,
+        height as u32,
+        (PIX_SIZE * DISPLAY_HEIGHT / height) as u32,
     )
     .with_margin(5, 5)
     .build()
     .unwrap();
 
-    let cpu = cpu::CPU::new();
-    let clock_time = matches
-        .value_of("clock_time")
-        .unwrap_or("2")
-        .parse::<u64>()
-        .expect("invalid clock value");
-
-    // should be handled with polymorphism, but it's complicated...
-    match matches.occurrences_of("debug") {
-        1 => {
-            let mut chip8 = chip8::Chip8::new(display, debugger::Debugger::new(cpu));
-            chip8.load(&filename);
-            chip8.run(clock_time);
+    let mut chip8 = match matches.occurrences_of("debug") {
+        1 => chip8::Chip8::new(display, debugger::Debugger::new(cpu)),
+        _ if matches.is_present("vip") => {
+            let interpreter =
+                fs::read(matches.value_of("vip").unwrap()).expect("could not read the interpreter");
+            let monitor = matches.value_of("monitor").map_or_else(Vec::new, |path| {
+                fs::read(path).expect("could not read the monitor ROM")
+            });
+            chip8::Chip8::new(display, vip::Vip::new(&interpreter, &monitor))
         }
-        _ => {
-            let mut chip8 = chip8::Chip8::new(display, cpu);
-            chip8.load(&filename);
-            chip8.run(clock_time);
+        #[cfg(feature = "recompiler")]
+        _ if matches.is_present("recompiler") => {
+            chip8::Chip8::new(display, recompiler::Recompiler::new(cpu))
         }
+        _ => chip8::Chip8::new(display, cpu),
+    };
+    chip8.set_filter(filter);
+    chip8.set_tone(tone);
+    chip8.set_speed(speed);
+    // movies start from blank flags, to be replayed identically.
+    if matches.is_present("record") || matches.is_present("play") {
+        chip8.set_flag_store(None);
+    } else if let Some(dir) = matches.value_of("flags_dir") {
+        chip8.set_flag_store(Some(FlagStore::new(dir)));
+    }
+    start(chip8, filename, movie, &matches);
+}
+
+fn start(mut chip8: chip8::Chip8, filename: &str, movie: movie::Movie, matches: &ArgMatches) {
+    // the rom runs as soon as it is loaded, everything applying to its first frame comes before.
+    chip8.set_ipf(movie.ipf);
+    chip8.set_audio_sync(matches.is_present("audio_sync"));
+    if let Some(path) = matches.value_of("wav") {
+        chip8
+            .record_audio(path)
+            .expect("could not create the WAV file");
+    }
+    if let Some(path) = matches.value_of("record") {
+        chip8.record_movie(movie, path);
+    } else if matches.is_present("play") {
+        chip8.play_movie(movie);
+    }
+    chip8.load(filename);
+    chip8.run();
+}
+
+fn bench(matches: &ArgMatches) {
+    let filename = matches.value_of("ROM").unwrap();
+    let frames = matches
+        .value_of("frames")
+        .map_or(6000, |n| n.parse::<u64>().expect("invalid frames value"));
+    let ipf = matches
+        .value_of("ipf")
+        .map_or(1000, |ipf| ipf.parse::<u32>().expect("invalid ipf value"));
+    let rom = fs::read(filename).expect("could not read the rom");
+
+    let mut cpu = cpu::CPU::new();
+    cpu.set_decode_cache(matches.is_present("decode_cache"));
+    cpu.load_rom(&rom);
+    let start = Instant::now();
+    #[cfg(feature = "recompiler")]
+    let ticks = if matches.is_present("recompiler") {
+        run_headless(recompiler::Recompiler::new(cpu), ipf, frames)
+    } else {
+        run_headless(cpu, ipf, frames)
+    };
+    #[cfg(not(feature = "recompiler"))]
+    let ticks = run_headless(cpu, ipf, frames);
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "{} frames, {} instructions in {:.3}s: {:.0} IPS",
+        frames,
+        ticks,
+        elapsed,
+        ticks as f64 / elapsed
+    );
+}
+
+// The counts that nothing could run with at 0.
+fn positive(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(()),
+        _ => Err(format!("{} is not a number above 0", value)),
+    }
+}
+
+// The font of --font or --font-file, at --font-address.
+fn font(matches: &ArgMatches) -> Font {
+    let mut font = match matches.value_of("font_file") {
+        Some(path) => Font::load(path).expect("could not load the font"),
+        None => Font::new(
+            matches
+                .value_of("font")
+                .map_or(FontSet::Chip8, |name| FontSet::from_name(name).unwrap()),
+        ),
+    };
+    if let Some(address) = matches.value_of("font_address") {
+        font.address = usize::from_str_radix(address.trim_start_matches("0x"), 16)
+            .expect("invalid font address");
+    }
+    font
+}
+
+fn run_headless<T: Processor>(cpu: T, ipf: u32, frames: u64) -> u64 {
+    Headless::new(cpu, ipf).run_frames(frames)
+}
+
+fn check_recompiler(matches: &ArgMatches) {
+    if cfg!(not(feature = "recompiler")) && matches.is_present("recompiler") {
+        eprintln!("this build doesn't include the recompiler, rebuild with --features recompiler");
+        std::process::exit(1);
     }
 }
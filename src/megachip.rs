@@ -0,0 +1,252 @@
+use crate::config::*;
+use crate::framebuffer::{Framebuffer, Rect};
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 192;
+/// Digitised sounds start with the sample rate on 2 bytes and the length on 3, then a reserved
+/// byte, followed by unsigned 8 bit samples.
+pub const SAMPLE_HEADER_LEN: usize = 6;
+// the monochrome screen is shown 4 times bigger when MegaChip mode is off, in the middle.
+const SCALE: usize = WIDTH / DISPLAY_WIDTH;
+const TOP: usize = (HEIGHT - DISPLAY_HEIGHT * SCALE) / 2;
+const FRAME_RATE: u32 = 60;
+
+/// How the sprites are mixed with the screen, set by 080n.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Blend {
+    Normal,
+    /// The sprites are drawn with a quarter of their opacity.
+    Quarter,
+    Half,
+    Add,
+    Multiply,
+}
+
+impl Blend {
+    pub fn from_mode(mode: u8) -> Self {
+        match mode {
+            1 => Blend::Quarter,
+            2 => Blend::Half,
+            3 => Blend::Add,
+            4 => Blend::Multiply,
+            _ => Blend::Normal,
+        }
+    }
+
+    /// Mixes the colour `src` of opacity `alpha` over `dst`.
+    fn mix(self, src: (u8, u8, u8), alpha: u8, dst: (u8, u8, u8)) -> (u8, u8, u8) {
+        let alpha = match self {
+            Blend::Quarter => alpha as u32 / 4,
+            Blend::Half => alpha as u32 / 2,
+            _ => alpha as u32,
+        };
+        let mix = |s: u8, d: u8| {
+            let (s, d) = (s as u32, d as u32);
+            let value = match self {
+                Blend::Add => d + s * alpha / 255,
+                Blend::Multiply => (d * (255 - alpha) + d * s / 255 * alpha) / 255,
+                _ => (s * alpha + d * (255 - alpha)) / 255,
+            };
+            value.min(255) as u8
+        };
+        (mix(src.0, dst.0), mix(src.1, dst.1), mix(src.2, dst.2))
+    }
+}
+
+struct Sample {
+    rate: u32,
+    data: Vec<u8>,
+    looping: bool,
+    position: usize,
+    // the fraction of a sample carried over to the next frame, in 1/60s.
+    remainder: u32,
+}
+
+/// The MegaChip extensions of `CPU`: a 256x192 screen of 256 colours with alpha, sprites of any
+/// size, and the playback of digitised sounds.
+///
+/// The sprites are drawn to a hidden screen, which is shown by the next clear.
+pub struct MegaChip {
+    /// Switched by 0011 and 0010, the monochrome screen is used when off.
+    pub enabled: bool,
+    // ARGB, 0 is transparent.
+    palette: [(u8, u8, u8, u8); 256],
+    pub sprite_width: usize,
+    pub sprite_height: usize,
+    /// The opacity of the whole screen.
+    pub alpha: u8,
+    pub blend: Blend,
+    /// Drawing over a pixel of this colour is a collision.
+    pub collision: u8,
+    // the screen being drawn, as colour indices for the collisions and as the mixed colours.
+    indices: Vec<u8>,
+    back: Vec<(u8, u8, u8)>,
+    front: Vec<(u8, u8, u8)>,
+    dirty: bool,
+    sample: Option<Sample>,
+}
+
+impl MegaChip {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            palette: [(0, 0, 0, 0); 256],
+            sprite_width: 0,
+            sprite_height: 0,
+            alpha: 0xff,
+            blend: Blend::Normal,
+            collision: 0,
+            indices: vec![0; WIDTH * HEIGHT],
+            back: vec![(0, 0, 0); WIDTH * HEIGHT],
+            front: vec![(0, 0, 0); WIDTH * HEIGHT],
+            dirty: true,
+            sample: None,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.dirty = true;
+    }
+
+    /// Sets the colours from 1 on, from 4 bytes each: alpha, red, green and blue.
+    pub fn load_palette(&mut self, colors: &[u8]) {
+        for (color, argb) in self.palette[1..].iter_mut().zip(colors.chunks_exact(4)) {
+            *color = (argb[0], argb[1], argb[2], argb[3]);
+        }
+    }
+
+    /// Shows the screen drawn so far, and starts a blank one.
+    pub fn clear(&mut self) {
+        std::mem::swap(&mut self.front, &mut self.back);
+        self.back.iter_mut().for_each(|p| *p = (0, 0, 0));
+        self.indices.iter_mut().for_each(|p| *p = 0);
+        self.dirty = true;
+    }
+
+    /// Draws a sprite of `sprite_width` x `sprite_height` colour indices, clipped at the edges of
+    /// the screen. Returns whether it collided.
+    pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        let mut collision = false;
+        for (row, line) in sprite.chunks(self.sprite_width.max(1)).enumerate() {
+            for (column, &index) in line.iter().enumerate() {
+                let (px, py) = (x + column, y + row);
+                if index == 0 || px >= WIDTH || py >= HEIGHT {
+                    continue;
+                }
+                let p = py * WIDTH + px;
+                if self.indices[p] != 0 && self.indices[p] == self.collision {
+                    collision = true;
+                }
+                let (a, r, g, b) = self.palette[index as usize];
+                self.indices[p] = index;
+                self.back[p] = self.blend.mix((r, g, b), a, self.back[p]);
+            }
+        }
+        collision
+    }
+
+    pub fn play(&mut self, rate: u32, data: Vec<u8>, looping: bool) {
+        self.sample = Some(Sample {
+            rate,
+            data,
+            looping,
+            position: 0,
+            remainder: 0,
+        });
+    }
+
+    pub fn stop(&mut self) {
+        self.sample = None;
+    }
+
+    /// The samples of the sound playing for 1/60s, between -1 and 1.
+    pub fn take_samples(&mut self) -> Option<Vec<f32>> {
+        let sample = self.sample.as_mut()?;
+        let total = sample.rate + sample.remainder;
+        let count = (total / FRAME_RATE) as usize;
+        sample.remainder = total % FRAME_RATE;
+        let mut samples = Vec::with_capacity(count);
+        while samples.len() < count {
+            if sample.position == sample.data.len() {
+                if !sample.looping || sample.data.is_empty() {
+                    break;
+                }
+                sample.position = 0;
+            }
+            samples.push((sample.data[sample.position] as f32 - 128.0) / 128.0);
+            sample.position += 1;
+        }
+        samples.resize(count, 0.0);
+        let repeats = sample.looping && !sample.data.is_empty();
+        if sample.rate == 0 || (sample.position == sample.data.len() && !repeats) {
+            self.sample = None;
+        }
+        Some(samples)
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.dirty, false)
+    }
+
+    /// Writes the pixels of `rect` to a buffer of the whole screen, either in colour or the
+    /// monochrome `vram` scaled up.
+    pub fn write_region(&self, vram: &Framebuffer, buffer: &mut [(u8, u8, u8)], rect: Rect) {
+        let fade = |c: u8| (c as u32 * self.alpha as u32 / 255) as u8;
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                buffer[y * WIDTH + x] = if self.enabled {
+                    let (r, g, b) = self.front[y * WIDTH + x];
+                    (fade(r), fade(g), fade(b))
+                } else if (TOP..HEIGHT - TOP).contains(&y) && vram.get(x / SCALE, (y - TOP) / SCALE)
+                {
+                    (0xff, 0xff, 0xff)
+                } else {
+                    (0, 0, 0)
+                };
+            }
+        }
+    }
+}
+
+impl Default for MegaChip {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blend() {
+        let (white, grey) = ((0xff, 0xff, 0xff), (0x80, 0x80, 0x80));
+        assert_eq!(Blend::Normal.mix(white, 0xff, grey), white);
+        assert_eq!(Blend::Normal.mix(white, 0, grey), grey);
+        assert_eq!(Blend::Half.mix((0, 0, 0), 0xff, white), grey);
+        assert_eq!(Blend::Add.mix(grey, 0xff, grey), (0xff, 0xff, 0xff));
+        assert_eq!(Blend::Multiply.mix(grey, 0xff, white), grey);
+    }
+
+    #[test]
+    fn test_samples() {
+        let mut mega = MegaChip::new();
+        assert_eq!(mega.take_samples(), None);
+        // 90Hz: 1 then 2 samples per frame, the sound ends with the third.
+        mega.play(90, vec![0x80, 0xc0, 0x40, 0xff], false);
+        assert_eq!(mega.take_samples(), Some(vec![0.0]));
+        assert_eq!(mega.take_samples(), Some(vec![0.5, -0.5]));
+        assert_eq!(mega.take_samples(), Some(vec![127.0 / 128.0]));
+        assert_eq!(mega.take_samples(), None);
+
+        mega.play(120, vec![0x00], true);
+        assert_eq!(mega.take_samples(), Some(vec![-1.0, -1.0]));
+        mega.stop();
+        assert_eq!(mega.take_samples(), None);
+    }
+}
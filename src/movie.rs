@@ -0,0 +1,252 @@
+use crate::config::DEFAULT_IPF;
+use crate::font::{Font, FontSet, FONT_LEN};
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::rng::RngModel;
+use crate::timing::Timing;
+use std::fs;
+use std::io;
+
+const MAGIC: &str = "chip8-movie 1";
+
+/// A keypad press or release, applied before the frame `frame` is run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub down: bool,
+}
+
+/// A recording of the input of a session, with everything else needed to replay it identically.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub rng: RngModel,
+    pub ipf: u32,
+    pub quirks: Quirks,
+    pub timing: Timing,
+    pub platform: Platform,
+    /// The glyphs Fx29 and Fx30 point to, and where they are.
+    pub font: Font,
+    pub events: Vec<KeyEvent>,
+}
+
+impl Movie {
+    pub fn load(path: &str) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{}\nrom {:016x}\nseed {}\nrng {}\nipf {}\ndisplay_wait {}\ntiming {}\nplatform {}\n\
+             font {}\nfont_address {:x}\n",
+            MAGIC,
+            self.rom_hash,
+            self.seed,
+            rng_name(self.rng),
+            self.ipf,
+            self.quirks.display_wait as u8,
+            timing_name(self.timing),
+            self.platform.name(),
+            font_name(&self.font),
+            self.font.address,
+        );
+        for e in &self.events {
+            text.push_str(&format!("{} {:x} {}\n", e.frame, e.key, e.down as u8));
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+        if lines.next() != Some(MAGIC) {
+            return Err(String::from("not a movie file"));
+        }
+        let mut movie = Movie {
+            rom_hash: 0,
+            seed: 0,
+            rng: RngModel::Xorshift,
+            ipf: DEFAULT_IPF,
+            quirks: Quirks::default(),
+            timing: Timing::default(),
+            platform: Platform::default(),
+            font: Font::default(),
+            events: Vec::new(),
+        };
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || format!("invalid line: {}", line);
+            match fields.as_slice() {
+                ["rom", hash] => {
+                    movie.rom_hash = u64::from_str_radix(hash, 16).map_err(|_| invalid())?
+                }
+                ["seed", seed] => movie.seed = seed.parse().map_err(|_| invalid())?,
+                ["rng", name] => movie.rng = rng_model(name).ok_or_else(invalid)?,
+                ["ipf", ipf] => {
+                    movie.ipf = ipf
+                        .parse()
+                        .ok()
+                        .filter(|ipf| *ipf > 0)
+                        .ok_or_else(invalid)?
+                }
+                ["display_wait", wait] => movie.quirks.display_wait = *wait == "1",
+                ["timing", name] => movie.timing = timing(name).ok_or_else(invalid)?,
+                ["platform", name] => {
+                    movie.platform = Platform::from_name(name).ok_or_else(invalid)?
+                }
+                ["font", name] => {
+                    movie.font = Font {
+                        address: movie.font.address,
+                        ..font(name).ok_or_else(invalid)?
+                    }
+                }
+                ["font_address", address] => {
+                    movie.font.address =
+                        usize::from_str_radix(address, 16).map_err(|_| invalid())?
+                }
+                [frame, key, down] => {
+                    let event = KeyEvent {
+                        frame: frame.parse().map_err(|_| invalid())?,
+                        key: u8::from_str_radix(key, 16)
+                            .ok()
+                            .filter(|k| *k < 16)
+                            .ok_or_else(invalid)?,
+                        down: *down == "1",
+                    };
+                    if movie.events.last().is_some_and(|e| e.frame > event.frame) {
+                        return Err(format!("events are not in order: {}", line));
+                    }
+                    movie.events.push(event);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(movie)
+    }
+}
+
+// The name of a built-in font, or the hex of the glyphs of a user font.
+fn font_name(font: &Font) -> String {
+    match font.set() {
+        Some(set) => set.name().to_string(),
+        None => font.glyphs.iter().map(|b| format!("{:02x}", b)).collect(),
+    }
+}
+
+fn font(name: &str) -> Option<Font> {
+    if let Some(set) = FontSet::from_name(name) {
+        return Some(Font::new(set));
+    }
+    if name.len() != FONT_LEN * 2 || !name.is_ascii() {
+        return None;
+    }
+    let bytes = (0..FONT_LEN)
+        .map(|n| u8::from_str_radix(&name[n * 2..n * 2 + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Font::from_bytes(&bytes).ok()
+}
+
+pub fn rng_name(model: RngModel) -> &'static str {
+    match model {
+        RngModel::Xorshift => "xorshift",
+        RngModel::Lcg => "lcg",
+        RngModel::Vip => "vip",
+    }
+}
+
+pub fn rng_model(name: &str) -> Option<RngModel> {
+    match name {
+        "xorshift" => Some(RngModel::Xorshift),
+        "lcg" => Some(RngModel::Lcg),
+        "vip" => Some(RngModel::Vip),
+        _ => None,
+    }
+}
+
+pub fn timing_name(timing: Timing) -> &'static str {
+    match timing {
+        Timing::Ipf => "ipf",
+        Timing::Vip => "vip",
+    }
+}
+
+pub fn timing(name: &str) -> Option<Timing> {
+    match name {
+        "ipf" => Some(Timing::Ipf),
+        "vip" => Some(Timing::Vip),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_movie_round_trip() {
+        let movie = Movie {
+            rom_hash: 0xdead_beef,
+            seed: 42,
+            rng: RngModel::Vip,
+            ipf: 15,
+            quirks: Quirks::vip(),
+            timing: Timing::Vip,
+            platform: Platform::Eti660,
+            font: Font {
+                address: 0x50,
+                ..Font::new(FontSet::Dream6800)
+            },
+            events: vec![
+                KeyEvent {
+                    frame: 3,
+                    key: 0xa,
+                    down: true,
+                },
+                KeyEvent {
+                    frame: 3,
+                    key: 0x1,
+                    down: true,
+                },
+                KeyEvent {
+                    frame: 10,
+                    key: 0xa,
+                    down: false,
+                },
+            ],
+        };
+        let parsed = Movie::parse(&movie.to_text()).unwrap();
+        assert_eq!(parsed, movie);
+
+        // a user font is saved whole.
+        let mut glyphs = [0x55; FONT_LEN];
+        glyphs[0] = 0xf0;
+        let movie = Movie {
+            font: Font::from_bytes(&glyphs).unwrap(),
+            ..movie
+        };
+        assert_eq!(Movie::parse(&movie.to_text()).unwrap(), movie);
+    }
+
+    #[test]
+    fn test_movie_invalid() {
+        assert!(Movie::parse("hello").is_err());
+        assert!(Movie::parse(&format!("{}\n1 10 1\n", MAGIC)).is_err());
+        assert!(Movie::parse(&format!("{}\n5 1 1\n4 1 0\n", MAGIC)).is_err());
+        assert!(Movie::parse(&format!("{}\nipf 0\n", MAGIC)).is_err());
+        assert!(Movie::parse(&format!("{}\nfont f0f0\n", MAGIC)).is_err());
+    }
+
+    #[test]
+    fn test_movie_defaults() {
+        let movie = Movie::parse(&format!("{}\nseed 7\n", MAGIC)).unwrap();
+        assert_eq!(movie.seed, 7);
+        assert_eq!(movie.ipf, DEFAULT_IPF);
+        assert_eq!(movie.font, Font::default());
+    }
+}
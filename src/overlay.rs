@@ -0,0 +1,106 @@
+use crate::config::*;
+
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+
+// Draws the pause symbol (two vertical bars) in the top right corner of the screen buffer.
+pub fn draw_paused(buffer: &mut [(u8, u8, u8)], width: usize) {
+    let left = width - 7;
+    fill_rect(buffer, width, left, 1, 6, 7, BG_COLOR);
+    fill_rect(buffer, width, left + 1, 2, 1, 5, OSD_COLOR);
+    fill_rect(buffer, width, left + 4, 2, 1, 5, OSD_COLOR);
+}
+
+// Draws one line of text per entry of `lines` in the top left corner of the screen buffer.
+pub fn draw_lines(buffer: &mut [(u8, u8, u8)], width: usize, lines: &[&str]) {
+    let text_width = lines.iter().map(|l| text_width(l)).max().unwrap_or(0);
+    let height = lines.len() * (GLYPH_HEIGHT + 1);
+    fill_rect(buffer, width, 0, 0, text_width + 2, height + 1, BG_COLOR);
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(buffer, width, 1, 1 + i * (GLYPH_HEIGHT + 1), line);
+    }
+}
+
+/// Formats a count so that it fits the small on screen font, e.g. 12345 -> "12K".
+pub fn short_count(count: u64) -> String {
+    match count {
+        0..=9_999 => format!("{}", count),
+        10_000..=9_999_999 => format!("{}K", count / 1000),
+        _ => format!("{}M", count / 1_000_000),
+    }
+}
+
+fn text_width(text: &str) -> usize {
+    (text.chars().count() * (GLYPH_WIDTH + 1)).saturating_sub(1)
+}
+
+fn draw_text(buffer: &mut [(u8, u8, u8)], width: usize, x: usize, y: usize, text: &str) {
+    for (n, c) in text.chars().enumerate() {
+        let left = x + n * (GLYPH_WIDTH + 1);
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> col) != 0 {
+                    fill_rect(buffer, width, left + col, y + row, 1, 1, OSD_COLOR);
+                }
+            }
+        }
+    }
+}
+
+// 3x5 glyphs, one row per byte, most significant of the three bits on the left.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b011, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+// Fills a rectangle of a screen `width` pixels wide.
+fn fill_rect(
+    buffer: &mut [(u8, u8, u8)],
+    width: usize,
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+    color: (u8, u8, u8),
+) {
+    let height = buffer.len() / width;
+    for row in y..(y + h).min(height) {
+        for col in x..(x + w).min(width) {
+            buffer[row * width + col] = color;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_count() {
+        assert_eq!(short_count(0), "0");
+        assert_eq!(short_count(9_999), "9999");
+        assert_eq!(short_count(12_345), "12K");
+        assert_eq!(short_count(42_000_000), "42M");
+    }
+}
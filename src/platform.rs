@@ -0,0 +1,138 @@
+use crate::config::*;
+use crate::megachip;
+
+/// The CHIP-8 variants run by `CPU`, which differ by where programs are loaded, the size of the
+/// screen and a few instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Platform {
+    /// The original interpreter of the COSMAC VIP.
+    #[default]
+    Chip8,
+    /// Two-page hi-res CHIP-8 for the VIP, 64x64. The roms start by jumping to the hi-res
+    /// interpreter they carry at 0x260, whose instructions are implemented natively instead.
+    HiRes,
+    /// The interpreter of the ETI-660, which loads programs at 0x600 and shows 64x48.
+    Eti660,
+    /// CHIP-8X, for the VIP with the VP-590 colour board, loaded at 0x300.
+    Chip8X,
+    /// MegaChip, which switches to a 256x192 colour screen and addresses 16MB with I.
+    MegaChip,
+}
+
+impl Platform {
+    pub fn all() -> [Self; 5] {
+        [
+            Platform::Chip8,
+            Platform::HiRes,
+            Platform::Eti660,
+            Platform::Chip8X,
+            Platform::MegaChip,
+        ]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::HiRes => "hires",
+            Platform::Eti660 => "eti660",
+            Platform::Chip8X => "chip8x",
+            Platform::MegaChip => "megachip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().iter().copied().find(|p| p.name() == name)
+    }
+
+    /// Where the programs are loaded and started.
+    pub fn pgm_offset(self) -> usize {
+        match self {
+            Platform::Eti660 => 0x600,
+            Platform::Chip8X => 0x300,
+            _ => 0x200,
+        }
+    }
+
+    /// The height of the monochrome screen.
+    pub fn display_height(self) -> usize {
+        match self {
+            Platform::HiRes => 64,
+            Platform::Eti660 => 48,
+            _ => DISPLAY_HEIGHT,
+        }
+    }
+
+    /// The size of the picture shown, the colour screen of MegaChip or the monochrome one.
+    pub fn display_size(self) -> (usize, usize) {
+        match self {
+            Platform::MegaChip => (megachip::WIDTH, megachip::HEIGHT),
+            _ => (DISPLAY_WIDTH, self.display_height()),
+        }
+    }
+
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::MegaChip => 0x100_0000,
+            _ => 0x1000,
+        }
+    }
+}
+
+// the colours of the VP-590, for the foreground, and the backgrounds 02A0 cycles through.
+const FOREGROUND: [(u8, u8, u8); 8] = [
+    (0x00, 0x00, 0x00),
+    (0xff, 0x00, 0x00),
+    (0x00, 0x00, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+const BACKGROUND: [(u8, u8, u8); 4] = [
+    (0x00, 0x00, 0x80),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x80, 0x00),
+    (0x80, 0x00, 0x00),
+];
+const ZONE_WIDTH: usize = 8;
+const ZONE_COLUMNS: usize = DISPLAY_WIDTH / ZONE_WIDTH;
+const DEFAULT_COLOR: u8 = 1;
+
+/// The colour board of CHIP-8X: the foreground colour is set by zones 8 pixels wide and 1 high.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Colors {
+    background: usize,
+    zones: Vec<u8>,
+}
+
+impl Colors {
+    pub fn new(height: usize) -> Self {
+        Self {
+            background: 0,
+            zones: vec![DEFAULT_COLOR; ZONE_COLUMNS * height],
+        }
+    }
+
+    pub fn next_background(&mut self) {
+        self.background = (self.background + 1) % BACKGROUND.len();
+    }
+
+    /// Sets the colour of the zones from the column `column` and the row `row`.
+    pub fn fill(&mut self, column: usize, row: usize, columns: usize, rows: usize, color: u8) {
+        let height = self.zones.len() / ZONE_COLUMNS;
+        for y in row..(row + rows).min(height) {
+            for x in column..(column + columns).min(ZONE_COLUMNS) {
+                self.zones[y * ZONE_COLUMNS + x] = color & 7;
+            }
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize, lit: bool) -> (u8, u8, u8) {
+        if lit {
+            FOREGROUND[self.zones[y * ZONE_COLUMNS + x / ZONE_WIDTH] as usize]
+        } else {
+            BACKGROUND[self.background]
+        }
+    }
+}
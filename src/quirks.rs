@@ -0,0 +1,21 @@
+/// Behaviours that differ between CHIP-8 interpreters, and that some roms depend on.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Quirks {
+    /// Dxyn waits for the next vertical blank before drawing, like the COSMAC VIP interpreter.
+    /// This limits roms to one sprite per frame.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// The display wait of the original COSMAC VIP interpreter. It is the only quirk of the VIP
+    /// modelled: its shifts, loads and stores, jumps with offset and VF resets are not, they
+    /// behave the same in every preset.
+    pub fn vip() -> Self {
+        Self { display_wait: true }
+    }
+
+    /// Every preset, with its name.
+    pub fn presets() -> [(&'static str, Self); 2] {
+        [("default", Self::default()), ("vip", Self::vip())]
+    }
+}
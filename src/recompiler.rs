@@ -0,0 +1,290 @@
+use crate::cpu::{Frame, Processor, CPU};
+use crate::flags::FLAG_COUNT;
+use crate::framebuffer::Rect;
+use crate::instruction::Instruction;
+use std::sync::Arc;
+
+const MEMORY_SIZE: usize = 4096;
+const MAX_BLOCK_LEN: usize = 64;
+
+/// A straight-line run of decoded instructions, starting at `start`.
+struct Block {
+    start: usize,
+    instructions: Vec<Instruction>,
+}
+
+impl Block {
+    fn contains(&self, address: usize) -> bool {
+        // a block may wrap around the end of the memory.
+        (address.wrapping_sub(self.start) & (MEMORY_SIZE - 1)) < self.instructions.len() * 2
+    }
+}
+
+/// Runs the CPU by basic blocks: the instructions from an address up to the next one that can
+/// change the control flow are decoded once, then run without fetching nor decoding.
+///
+/// Code that the rom writes to is marked as self-modifying, and is interpreted from then on.
+pub struct Recompiler {
+    pub cpu: CPU,
+    blocks: Vec<Option<Arc<Block>>>,
+    /// The addresses that were ever compiled, to find quickly whether a write can hit a block.
+    compiled: Vec<bool>,
+    self_modifying: Vec<bool>,
+}
+
+impl Recompiler {
+    pub fn new(cpu: CPU) -> Self {
+        Self {
+            blocks: vec![None; MEMORY_SIZE],
+            compiled: vec![false; cpu.ram.len()],
+            self_modifying: vec![false; MEMORY_SIZE],
+            cpu,
+        }
+    }
+
+    /// Drops every compiled block, needed after writing to `cpu.ram` directly.
+    pub fn invalidate(&mut self) {
+        self.blocks.iter_mut().for_each(|block| *block = None);
+    }
+
+    fn clear(&mut self) {
+        self.invalidate();
+        // the memory grows or shrinks with the platform.
+        self.compiled.clear();
+        self.compiled.resize(self.cpu.ram.len(), false);
+        self.self_modifying.iter_mut().for_each(|m| *m = false);
+    }
+
+    fn compile(&mut self, start: usize) -> Block {
+        let mut instructions = Vec::new();
+        let mut address = start;
+        while instructions.len() < MAX_BLOCK_LEN && !self.self_modifying[address] {
+            let inst = self.cpu.decode_at(address);
+            instructions.push(inst);
+            self.compiled[address] = true;
+            self.compiled[(address + 1) & (MEMORY_SIZE - 1)] = true;
+            address = (address + 2) & (MEMORY_SIZE - 1);
+            if ends_block(inst) {
+                break;
+            }
+        }
+        Block {
+            start,
+            instructions,
+        }
+    }
+
+    /// Runs up to `max` instructions from the pc, stopping after the first one when the frame is
+    /// out of time. Returns how many ran.
+    fn run_block(&mut self, max: u32) -> u32 {
+        let pc = self.cpu.pc;
+        if self.self_modifying[pc] {
+            self.cpu.tick();
+            return 1;
+        }
+        let block = match &self.blocks[pc] {
+            Some(block) => Arc::clone(block),
+            None => {
+                let block = Arc::new(self.compile(pc));
+                self.blocks[pc] = Some(Arc::clone(&block));
+                block
+            }
+        };
+
+        let mut ticks = 0;
+        for &inst in block.instructions.iter() {
+            if ticks == max || self.cpu.waiting_vblank() || (ticks > 0 && !self.cpu.has_cycles()) {
+                break;
+            }
+            let written = self.written_range(inst);
+            self.cpu.step(inst);
+            ticks += 1;
+            if let Some((first, len)) = written {
+                self.written(first, len);
+            }
+        }
+        ticks
+    }
+
+    /// The memory an instruction is about to write to, as the first address and the length.
+    fn written_range(&self, inst: Instruction) -> Option<(usize, usize)> {
+        match inst {
+            Instruction::LdB(_) => Some((self.cpu.i, 3)),
+            Instruction::LdMemVx(x) => Some((self.cpu.i, x + 1)),
+            _ => None,
+        }
+    }
+
+    fn written(&mut self, first: usize, len: usize) {
+        // masked like the CPU's writes, only the first 4K can hold compiled code.
+        let mask = self.cpu.ram.len() - 1;
+        for offset in 0..len {
+            let address = (first + offset) & mask;
+            if !self.compiled[address] {
+                continue;
+            }
+            let mut hit = false;
+            for block in self.blocks.iter_mut() {
+                if block.as_ref().is_some_and(|b| b.contains(address)) {
+                    *block = None;
+                    hit = true;
+                }
+            }
+            if hit {
+                self.self_modifying[address] = true;
+                self.self_modifying[address.wrapping_sub(1) & (MEMORY_SIZE - 1)] = true;
+            }
+        }
+    }
+}
+
+/// Whether an instruction may not continue to the next one: jumps, skips, waits, the writes to
+/// memory, which may modify the block, and the instructions followed by data.
+fn ends_block(inst: Instruction) -> bool {
+    matches!(
+        inst,
+        Instruction::Ret
+            | Instruction::Jp(_)
+            | Instruction::Call(_)
+            | Instruction::SeImm(_, _)
+            | Instruction::SneImm(_, _)
+            | Instruction::Se(_, _)
+            | Instruction::Sne(_, _)
+            | Instruction::JpV0(_)
+            | Instruction::Drw(_, _, _)
+            | Instruction::Skp(_)
+            | Instruction::Sknp(_)
+            | Instruction::SkpPad2(_)
+            | Instruction::SknpPad2(_)
+            | Instruction::LdIFar(_)
+            | Instruction::LdVxK(_)
+            | Instruction::LdB(_)
+            | Instruction::LdMemVx(_)
+    )
+}
+
+impl Processor for Recompiler {
+    fn get_sound_timer(&self) -> u8 {
+        self.cpu.get_sound_timer()
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) {
+        self.cpu.load_rom(rom);
+        self.clear();
+    }
+
+    fn reset(&mut self) {
+        self.cpu.reset();
+        self.clear();
+    }
+
+    fn tick_timers(&mut self) {
+        self.cpu.tick_timers();
+    }
+
+    fn vblank(&mut self) {
+        self.cpu.vblank();
+    }
+
+    fn waiting_vblank(&self) -> bool {
+        self.cpu.waiting_vblank()
+    }
+
+    fn display_size(&self) -> (usize, usize) {
+        self.cpu.display_size()
+    }
+
+    fn take_samples(&mut self) -> Option<Vec<f32>> {
+        self.cpu.take_samples()
+    }
+
+    fn user_flags(&self) -> [u8; FLAG_COUNT] {
+        self.cpu.user_flags()
+    }
+
+    fn set_user_flags(&mut self, flags: [u8; FLAG_COUNT]) {
+        self.cpu.set_user_flags(flags);
+    }
+
+    fn get_vram_region(&self, buffer: &mut [(u8, u8, u8)], rect: Rect) {
+        self.cpu.get_vram_region(buffer, rect)
+    }
+
+    fn set_key_press(&mut self, key: u8, is_down: bool) {
+        self.cpu.set_key_press(key, is_down);
+    }
+
+    fn should_redraw(&self) -> bool {
+        self.cpu.should_redraw()
+    }
+
+    fn take_dirty(&mut self) -> Option<Rect> {
+        self.cpu.take_dirty()
+    }
+
+    fn tick(&mut self) {
+        self.run_block(1);
+    }
+
+    fn run_frame(&mut self, ipf: u32) -> Frame {
+        self.cpu.start_frame(ipf);
+        let mut ticks = 0;
+        while self.cpu.has_cycles() && !self.waiting_vblank() {
+            ticks += self.run_block(u32::MAX);
+        }
+        let beep = self.get_sound_timer() > 0;
+        self.tick_timers();
+        self.vblank();
+        Frame { ticks, beep }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+
+    #[test]
+    fn test_blocks() {
+        let mut recompiler = Recompiler::new(CPU::new());
+        // 0x200: LD V0, 1; 0x202: ADD V0, 1; 0x204: JMP 0x202
+        recompiler.load_rom(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]);
+        assert_eq!(recompiler.run_frame(7).ticks, 7);
+        assert_eq!(recompiler.cpu.v[0], 4);
+        assert_eq!(recompiler.cpu.pc, 0x202);
+        let block = recompiler.blocks[0x200].as_ref().unwrap();
+        assert_eq!(block.instructions.len(), 3);
+    }
+
+    #[test]
+    fn test_self_modifying() {
+        let mut recompiler = Recompiler::new(CPU::new());
+        // 0x200: LD I, 0x20a; 0x202: CALL 0x20a; 0x204: LD [I], V1; 0x206: CALL 0x20a
+        // 0x208: JMP 0x208; 0x20a: ADD V2, 1; 0x20c: RET
+        // the store rewrites the ADD at 0x20a, which was already compiled, into ADD V2, 5.
+        recompiler.load_rom(&[
+            0xa2, 0x0a, 0x22, 0x0a, 0xf1, 0x55, 0x22, 0x0a, 0x12, 0x08, 0x72, 0x01, 0x00, 0xee,
+        ]);
+        recompiler.cpu.v[0] = 0x72;
+        recompiler.cpu.v[1] = 0x05;
+        recompiler.run_frame(20);
+        assert_eq!(recompiler.cpu.v[2], 6);
+        assert!(recompiler.self_modifying[0x20a]);
+        assert!(recompiler.blocks[0x20a].is_none());
+    }
+
+    #[test]
+    fn test_megachip_writes() {
+        let mut cpu = CPU::new();
+        cpu.set_platform(Platform::MegaChip);
+        let mut recompiler = Recompiler::new(cpu);
+        // 0x200: LD I, 0x001204; 0x204: LD [I], V1; 0x206: JMP 0x206
+        // the store is above 4K, it must not be taken for a write to the code at 0x204.
+        recompiler.load_rom(&[0x01, 0x00, 0x12, 0x04, 0xf1, 0x55, 0x12, 0x06]);
+        recompiler.cpu.v[0] = 0xaa;
+        recompiler.run_frame(10);
+        assert_eq!(recompiler.cpu.ram[0x1204], 0xaa);
+        assert!(!recompiler.self_modifying[0x204]);
+        assert!(recompiler.blocks[0x204].is_some());
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt;
+
+/// The algorithm used to produce the random bytes of Cxnn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RngModel {
+    /// xorshift64*, the default.
+    Xorshift,
+    /// The linear congruential generator of C's `rand`, used by many older emulators.
+    Lcg,
+    /// The generator of the COSMAC VIP interpreter: a counter, also advanced by each 60Hz
+    /// interrupt, indexes the second page of the interpreter's code and the byte read is added to
+    /// the previous result. The page is given with `Rng::vip`.
+    Vip,
+}
+
+/// Where the VIP generator reads its bytes in the interpreter.
+const VIP_TABLE_OFFSET: usize = 0x100;
+
+/// Owned, seedable random number generator, so that runs can be reproduced.
+#[derive(Clone)]
+pub struct Rng {
+    model: RngModel,
+    seed: u64,
+    state: u64,
+    table: [u8; 256],
+}
+
+impl Rng {
+    /// The VIP model reads all 0 here, it needs the interpreter of `Rng::vip`.
+    pub fn new(model: RngModel, seed: u64) -> Self {
+        let mut rng = Self {
+            model,
+            seed,
+            state: 0,
+            table: [0; 256],
+        };
+        rng.restart();
+        rng
+    }
+
+    /// The generator of the VIP, reading the code of `interpreter`, the CHIP-8 interpreter image.
+    pub fn vip(seed: u64, interpreter: &[u8]) -> Self {
+        let mut rng = Self::new(RngModel::Vip, seed);
+        let page = interpreter.get(VIP_TABLE_OFFSET..).unwrap_or_default();
+        let len = page.len().min(rng.table.len());
+        rng.table[..len].copy_from_slice(&page[..len]);
+        rng
+    }
+
+    pub fn model(&self) -> RngModel {
+        self.model
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Starts the sequence over from the seed.
+    pub fn restart(&mut self) {
+        self.state = match self.model {
+            // xorshift gets stuck on 0.
+            RngModel::Xorshift if self.seed == 0 => 0x9e37_79b9_7f4a_7c15,
+            RngModel::Xorshift => self.seed,
+            RngModel::Lcg => self.seed & 0x7fff_ffff,
+            RngModel::Vip => self.seed & 0xffff,
+        };
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        match self.model {
+            RngModel::Xorshift => {
+                self.state ^= self.state >> 12;
+                self.state ^= self.state << 25;
+                self.state ^= self.state >> 27;
+                (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+            }
+            RngModel::Lcg => {
+                self.state = (self.state.wrapping_mul(1_103_515_245) + 12345) & 0x7fff_ffff;
+                (self.state >> 16) as u8
+            }
+            RngModel::Vip => {
+                // the low byte is the counter, the high byte the previous result, like R9.
+                let counter = (self.state as u8).wrapping_add(1);
+                let value = ((self.state >> 8) as u8).wrapping_add(self.table[counter as usize]);
+                self.state = (value as u64) << 8 | counter as u64;
+                value
+            }
+        }
+    }
+
+    /// Called on each 60Hz interrupt, which advances the counter of the VIP generator.
+    pub fn tick_frame(&mut self) {
+        if self.model == RngModel::Vip {
+            self.state = self.state & 0xff00 | (self.state as u8).wrapping_add(1) as u64;
+        }
+    }
+}
+
+impl fmt::Debug for Rng {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?}(seed: {}, state: 0x{:x})",
+            self.model, self.seed, self.state
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interpreter() -> Vec<u8> {
+        (0..512).map(|n| (n * 37 + 11) as u8).collect()
+    }
+
+    fn rng(model: RngModel, seed: u64) -> Rng {
+        match model {
+            RngModel::Vip => Rng::vip(seed, &interpreter()),
+            _ => Rng::new(model, seed),
+        }
+    }
+
+    #[test]
+    fn test_rng_reproducible() {
+        for &model in &[RngModel::Xorshift, RngModel::Lcg, RngModel::Vip] {
+            let mut a = rng(model, 42);
+            let mut b = rng(model, 42);
+            let first: Vec<u8> = (0..64).map(|_| a.next_byte()).collect();
+            assert_eq!(first, (0..64).map(|_| b.next_byte()).collect::<Vec<_>>());
+            // not constant
+            assert!(first.iter().any(|x| *x != first[0]));
+            a.restart();
+            assert_eq!(first, (0..64).map(|_| a.next_byte()).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_rng_seeds_differ() {
+        let mut a = Rng::new(RngModel::Xorshift, 1);
+        let mut b = Rng::new(RngModel::Xorshift, 2);
+        let a: Vec<u8> = (0..16).map(|_| a.next_byte()).collect();
+        let b: Vec<u8> = (0..16).map(|_| b.next_byte()).collect();
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_rng_vip() {
+        let code = interpreter();
+        // R9 starts at 0x0100: the counter is 0 and the previous result 1.
+        let mut rng = Rng::vip(0x0100, &code);
+        assert_eq!(rng.next_byte(), 1u8.wrapping_add(code[0x101]));
+        let previous = 1u8.wrapping_add(code[0x101]);
+        assert_eq!(rng.next_byte(), previous.wrapping_add(code[0x102]));
+
+        // the interrupts move the counter between the calls.
+        rng.restart();
+        rng.tick_frame();
+        rng.tick_frame();
+        assert_eq!(rng.next_byte(), 1u8.wrapping_add(code[0x103]));
+    }
+}
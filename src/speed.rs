@@ -0,0 +1,140 @@
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    Multiplier(f64),
+    Unlimited,
+}
+
+const LEVELS: [Speed; 8] = [
+    Speed::Multiplier(0.25),
+    Speed::Multiplier(0.5),
+    Speed::Multiplier(1.0),
+    Speed::Multiplier(2.0),
+    Speed::Multiplier(4.0),
+    Speed::Multiplier(8.0),
+    Speed::Multiplier(16.0),
+    Speed::Unlimited,
+];
+
+impl Speed {
+    pub fn normal() -> Self {
+        Speed::Multiplier(1.0)
+    }
+
+    pub fn faster(self) -> Self {
+        LEVELS
+            .iter()
+            .find(|level| level.rank() > self.rank())
+            .cloned()
+            .unwrap_or(Speed::Unlimited)
+    }
+
+    pub fn slower(self) -> Self {
+        LEVELS
+            .iter()
+            .rev()
+            .find(|level| level.rank() < self.rank())
+            .cloned()
+            .unwrap_or(LEVELS[0])
+    }
+
+    /// Parses a multiplier, which must be above 0, or "max".
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "max" => Some(Speed::Unlimited),
+            _ => text
+                .parse::<f64>()
+                .ok()
+                .filter(|m| m.is_finite() && *m > 0.0)
+                .map(Speed::Multiplier),
+        }
+    }
+
+    /// Scales the time a step takes at normal speed. `None` means the step should not be waited on,
+    /// which is also the case of the multipliers that aren't above 0.
+    pub fn scale(self, duration: Duration) -> Option<Duration> {
+        match self {
+            Speed::Multiplier(m) if m.is_finite() && m > 0.0 => {
+                Duration::try_from_secs_f64(duration.as_secs_f64() / m).ok()
+            }
+            _ => None,
+        }
+    }
+
+    fn rank(self) -> f64 {
+        match self {
+            Speed::Multiplier(m) => m,
+            Speed::Unlimited => f64::INFINITY,
+        }
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Speed::Multiplier(m) => write!(f, "{}X", m),
+            Speed::Unlimited => write!(f, "MAX"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speed_levels() {
+        let mut speed = Speed::normal();
+        speed = speed.faster();
+        assert_eq!(speed, Speed::Multiplier(2.0));
+        for _ in 0..10 {
+            speed = speed.faster();
+        }
+        assert_eq!(speed, Speed::Unlimited);
+        assert_eq!(speed.slower(), Speed::Multiplier(16.0));
+        for _ in 0..10 {
+            speed = speed.slower();
+        }
+        assert_eq!(speed, Speed::Multiplier(0.25));
+        assert_eq!(Speed::Multiplier(3.0).faster(), Speed::Multiplier(4.0));
+        assert_eq!(Speed::Multiplier(3.0).slower(), Speed::Multiplier(2.0));
+    }
+
+    #[test]
+    fn test_speed_scale() {
+        let step = Duration::from_millis(2);
+        assert_eq!(Speed::normal().scale(step), Some(step));
+        assert_eq!(
+            Speed::Multiplier(0.25).scale(step),
+            Some(Duration::from_millis(8))
+        );
+        assert_eq!(
+            Speed::Multiplier(4.0).scale(step),
+            Some(Duration::from_micros(500))
+        );
+        assert_eq!(Speed::Unlimited.scale(step), None);
+        assert_eq!(Speed::Multiplier(0.0).scale(step), None);
+        assert_eq!(Speed::Multiplier(-1.0).scale(step), None);
+        assert_eq!(Speed::Multiplier(f64::NAN).scale(step), None);
+    }
+
+    #[test]
+    fn test_speed_parse() {
+        assert_eq!(Speed::parse("max"), Some(Speed::Unlimited));
+        assert_eq!(Speed::parse("0.5"), Some(Speed::Multiplier(0.5)));
+        assert_eq!(Speed::parse("0"), None);
+        assert_eq!(Speed::parse("-2"), None);
+        assert_eq!(Speed::parse("inf"), None);
+        assert_eq!(Speed::parse("NaN"), None);
+        assert_eq!(Speed::parse("fast"), None);
+    }
+
+    #[test]
+    fn test_speed_display() {
+        assert_eq!(Speed::Multiplier(0.25).to_string(), "0.25X");
+        assert_eq!(Speed::normal().to_string(), "1X");
+        assert_eq!(Speed::Unlimited.to_string(), "MAX");
+    }
+}
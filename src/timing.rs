@@ -0,0 +1,152 @@
+use crate::cpu::CPU;
+use crate::instruction::Instruction;
+
+/// The 1802 of the VIP runs at 1.76064MHz, with 8 clock cycles per machine cycle.
+const VIP_CYCLES_PER_SECOND: u32 = 1_760_640 / 8;
+// the display DMA steals 8 cycles on each of the 128 lines shown, and the interrupt routine
+// updating the timers runs once per frame.
+const VIP_DISPLAY_CYCLES: u32 = 128 * 8;
+const VIP_INTERRUPT_CYCLES: u32 = 46;
+// the interpreter loop fetching and decoding each instruction.
+const VIP_FETCH_CYCLES: u32 = 40;
+
+/// How long the instructions take, which sets how many of them run in a 60Hz frame.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Timing {
+    /// Every instruction takes the same time, `ipf` of them run per frame.
+    #[default]
+    Ipf,
+    /// Each instruction takes as many machine cycles as on the COSMAC VIP interpreter, out of
+    /// the cycles left per frame by the display and the interrupt routine.
+    Vip,
+}
+
+impl Timing {
+    /// The time available in a frame, in the unit of `cost`.
+    pub fn frame_budget(self, ipf: u32) -> u32 {
+        match self {
+            Timing::Ipf => ipf,
+            Timing::Vip => VIP_CYCLES_PER_SECOND / 60 - VIP_DISPLAY_CYCLES - VIP_INTERRUPT_CYCLES,
+        }
+    }
+
+    /// The time `inst` takes, executed by `cpu` in its current state.
+    pub fn cost(self, cpu: &CPU, inst: Instruction) -> u32 {
+        match self {
+            Timing::Ipf => 1,
+            Timing::Vip => VIP_FETCH_CYCLES + vip_cycles(cpu, inst),
+        }
+    }
+}
+
+// The execution time of an instruction on the VIP interpreter, in machine cycles, following the
+// published analyses of its code. Skips cost more when taken.
+fn vip_cycles(cpu: &CPU, inst: Instruction) -> u32 {
+    let skip = |taken: bool| if taken { 4 } else { 0 };
+    let key = |x: usize| cpu.key_press[cpu.v[x] as usize & 0xf];
+    match inst {
+        // clearing the 256 bytes of the display memory.
+        Instruction::Cls => 3078,
+        Instruction::Ret => 10,
+        Instruction::Jp(_) => 12,
+        Instruction::Call(_) => 26,
+        Instruction::SeImm(x, nn) => 10 + skip(cpu.v[x] == nn),
+        Instruction::SneImm(x, nn) => 10 + skip(cpu.v[x] != nn),
+        Instruction::Se(x, y) => 14 + skip(cpu.v[x] == cpu.v[y]),
+        Instruction::Sne(x, y) => 14 + skip(cpu.v[x] != cpu.v[y]),
+        Instruction::LdImm(_, _) => 6,
+        Instruction::AddImm(_, _) => 10,
+        Instruction::Ld(_, _)
+        | Instruction::Or(_, _)
+        | Instruction::And(_, _)
+        | Instruction::Xor(_, _)
+        | Instruction::Add(_, _)
+        | Instruction::Sub(_, _)
+        | Instruction::Shr(_, _)
+        | Instruction::Subn(_, _)
+        | Instruction::Shl(_, _) => 44,
+        Instruction::LdI(_) => 12,
+        // crossing a page takes an extra carry.
+        Instruction::JpV0(nnn) => {
+            22 + if (nnn & 0xff) + cpu.v[0] as usize > 0xff {
+                2
+            } else {
+                0
+            }
+        }
+        Instruction::Rnd(_, _) => 36,
+        Instruction::Drw(x, _, n) => drw_cycles(cpu.v[x], n),
+        Instruction::Skp(x) => 14 + skip(key(x)),
+        Instruction::Sknp(x) => 14 + skip(!key(x)),
+        Instruction::LdVxDt(_) => 10,
+        // the cost of one poll of the keypad.
+        Instruction::LdVxK(_) => 19,
+        Instruction::LdDtVx(_) | Instruction::LdStVx(_) => 10,
+        Instruction::AddI(_) => 16,
+        // the VIP has no big font, it is found like the small one.
+        Instruction::LdF(_) | Instruction::LdHf(_) => 16,
+        // each digit is computed by repeated subtractions.
+        Instruction::LdB(x) => {
+            let v = cpu.v[x] as u32;
+            84 + 16 * (v / 100 + v / 10 % 10 + v % 10)
+        }
+        // the VIP has no user flags, they are copied like the memory.
+        Instruction::LdMemVx(x)
+        | Instruction::LdVxMem(x)
+        | Instruction::LdFlagsVx(x)
+        | Instruction::LdVxFlags(x) => 14 + 14 * (x as u32 + 1),
+        // the instructions of CHIP-8X, and of MegaChip which has no VIP interpreter, count like
+        // the arithmetic ones.
+        Instruction::BgColor
+        | Instruction::AddColors(_, _)
+        | Instruction::Color(_, _, _)
+        | Instruction::SkpPad2(_)
+        | Instruction::SknpPad2(_)
+        | Instruction::Out(_)
+        | Instruction::In(_)
+        | Instruction::MegaOff
+        | Instruction::MegaOn
+        | Instruction::LdIFar(_)
+        | Instruction::LdPalette(_)
+        | Instruction::SpriteWidth(_)
+        | Instruction::SpriteHeight(_)
+        | Instruction::ScreenAlpha(_)
+        | Instruction::PlaySample(_)
+        | Instruction::StopSample
+        | Instruction::BlendMode(_)
+        | Instruction::CollisionColor(_) => 44,
+        Instruction::Unknown => 0,
+    }
+}
+
+// Dxyn shifts each sprite row into place one bit at a time, and a row that isn't aligned on a
+// byte spans two bytes of the display memory.
+fn drw_cycles(vx: u8, n: u8) -> u32 {
+    let shift = (vx % 8) as u32;
+    let row = if shift == 0 { 34 } else { 46 + 4 * shift };
+    26 + row * n as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vip_cycles() {
+        let mut cpu = CPU::new();
+        let cost = |cpu: &CPU, inst| Timing::Vip.cost(cpu, inst);
+        assert_eq!(Timing::Ipf.cost(&cpu, Instruction::Cls), 1);
+        assert_eq!(Timing::Vip.frame_budget(10), 2598);
+
+        // skips cost more when taken.
+        assert!(cost(&cpu, Instruction::SeImm(0, 0)) > cost(&cpu, Instruction::SeImm(0, 1)));
+
+        // sprites cost more when taller, and when not aligned on a byte.
+        assert!(cost(&cpu, Instruction::Drw(0, 0, 5)) > cost(&cpu, Instruction::Drw(0, 0, 1)));
+        cpu.v[1] = 3;
+        assert!(cost(&cpu, Instruction::Drw(1, 0, 5)) > cost(&cpu, Instruction::Drw(0, 0, 5)));
+
+        cpu.v[2] = 199;
+        assert!(cost(&cpu, Instruction::LdB(2)) > cost(&cpu, Instruction::LdB(0)));
+    }
+}
@@ -0,0 +1,296 @@
+use crate::cdp1802::{Bus, Cdp1802};
+use crate::cpu::{Frame, Processor};
+use crate::framebuffer::{Framebuffer, Rect};
+
+const RAM_SIZE: usize = 4096;
+const MONITOR_SIZE: usize = 512;
+const PGM_OFFSET: usize = 0x200;
+
+// the CDP1861 scans 262 lines of 14 machine cycles per frame. On each of the 128 lines shown,
+// it takes 8 bytes of the display memory by DMA.
+const LINE_CYCLES: u32 = 14;
+const FRAME_CYCLES: u32 = 262 * LINE_CYCLES;
+const FIRST_LINE: u32 = 80;
+const DISPLAY_LINES: u32 = 128;
+const DMA_CYCLES: u32 = 8;
+// the interrupt comes just early enough for the interpreter's routine to point R0 to the display
+// memory before the first DMA.
+const INTERRUPT_CYCLE: u32 = FIRST_LINE * LINE_CYCLES - 33;
+// EF1 is set during the 4 lines before the display starts, and before it ends.
+const EF1_LINES: u32 = 4;
+
+/// The memory map and the peripherals of the VIP, as seen by the 1802.
+pub struct Memory {
+    pub ram: [u8; RAM_SIZE],
+    /// The monitor ROM, mirrored from 8000 up.
+    monitor: Vec<u8>,
+    display_on: bool,
+    ef1: bool,
+    keys: [bool; 16],
+    // the key selected by OUT 2, whose state is read on EF3.
+    key_latch: usize,
+}
+
+impl Bus for Memory {
+    fn read(&mut self, address: u16) -> u8 {
+        let address = address as usize;
+        if address & 0x8000 != 0 {
+            // without a monitor ROM, nothing drives the bus.
+            self.monitor
+                .get(address & (MONITOR_SIZE - 1))
+                .copied()
+                .unwrap_or(0)
+        } else {
+            self.ram[address & (RAM_SIZE - 1)]
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address & 0x8000 == 0 {
+            self.ram[address as usize & (RAM_SIZE - 1)] = value;
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        // INP 1 turns the display on.
+        if port == 1 {
+            self.display_on = true;
+        }
+        0
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            // OUT 1 turns the display off.
+            1 => self.display_on = false,
+            2 => self.key_latch = value as usize & 0xf,
+            _ => (),
+        }
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        match flag {
+            1 => self.ef1,
+            3 => self.keys[self.key_latch],
+            _ => false,
+        }
+    }
+}
+
+/// A COSMAC VIP running the original CHIP-8 interpreter on an emulated 1802, with the CDP1861
+/// video chip and the hex keypad.
+///
+/// The interpreter image is loaded at 0000 and started from there, like the VIP does when it is
+/// switched to run, and the programs at 0200. The interpreter takes the font from the monitor
+/// ROM, which is optional otherwise.
+pub struct Vip {
+    pub cpu: Cdp1802,
+    pub memory: Memory,
+    interpreter: Vec<u8>,
+    // the machine cycle of the current frame, and what the 1861 did in it.
+    cycle: u32,
+    interrupted: bool,
+    dma_lines: u32,
+    vram: Framebuffer,
+}
+
+impl Vip {
+    pub fn new(interpreter: &[u8], monitor: &[u8]) -> Self {
+        let mut vip = Self {
+            cpu: Cdp1802::new(),
+            memory: Memory {
+                ram: [0; RAM_SIZE],
+                monitor: monitor[..monitor.len().min(MONITOR_SIZE)].to_vec(),
+                display_on: false,
+                ef1: false,
+                keys: [false; 16],
+                key_latch: 0,
+            },
+            interpreter: interpreter[..interpreter.len().min(RAM_SIZE)].to_vec(),
+            cycle: 0,
+            interrupted: false,
+            dma_lines: 0,
+            vram: Framebuffer::new(),
+        };
+        vip.reset();
+        vip
+    }
+
+    // Runs the next event: the interrupt or a DMA line of the 1861, or an instruction. Returns
+    // whether an instruction was executed.
+    fn advance(&mut self) -> bool {
+        let display = self.memory.display_on;
+        let line = self.cycle / LINE_CYCLES;
+        let last_line = FIRST_LINE + DISPLAY_LINES;
+        self.memory.ef1 = display
+            && ((FIRST_LINE - EF1_LINES..FIRST_LINE).contains(&line)
+                || (last_line - EF1_LINES..last_line).contains(&line));
+
+        // the interrupt request is held until the display starts.
+        if display
+            && !self.interrupted
+            && (INTERRUPT_CYCLE..FIRST_LINE * LINE_CYCLES).contains(&self.cycle)
+        {
+            let cycles = self.cpu.interrupt();
+            if cycles > 0 {
+                self.interrupted = true;
+                self.cycle += cycles;
+                return false;
+            }
+        }
+        if display
+            && self.dma_lines < DISPLAY_LINES
+            && self.cycle >= (FIRST_LINE + self.dma_lines) * LINE_CYCLES
+        {
+            self.dma_line();
+            self.cycle += DMA_CYCLES;
+            return false;
+        }
+        let idle = self.cpu.idle;
+        self.cycle += self.cpu.step(&mut self.memory);
+        !idle
+    }
+
+    // The interpreter shows each row of its display memory on 4 lines, the first is kept.
+    fn dma_line(&mut self) {
+        let mut bits = 0;
+        for _ in 0..DMA_CYCLES {
+            bits = bits << 8 | self.cpu.dma_out(&mut self.memory) as u64;
+        }
+        if self.dma_lines.is_multiple_of(4) {
+            self.vram.set_row(self.dma_lines as usize / 4, bits);
+        }
+        self.dma_lines += 1;
+    }
+
+    // Starts the next frame once the current one is over, returns whether it was.
+    fn end_frame(&mut self) -> bool {
+        if self.cycle < FRAME_CYCLES {
+            return false;
+        }
+        self.cycle -= FRAME_CYCLES;
+        self.interrupted = false;
+        self.dma_lines = 0;
+        if !self.memory.display_on && self.vram.rows().iter().any(|&row| row != 0) {
+            self.vram.clear();
+        }
+        true
+    }
+}
+
+impl Processor for Vip {
+    fn tick(&mut self) {
+        // an idle processor waits for the 1861 at most for a frame.
+        for _ in 0..FRAME_CYCLES {
+            let executed = self.advance();
+            self.end_frame();
+            if executed {
+                break;
+            }
+        }
+    }
+
+    fn should_redraw(&self) -> bool {
+        self.vram.is_dirty()
+    }
+
+    fn take_dirty(&mut self) -> Option<Rect> {
+        self.vram.take_dirty()
+    }
+
+    fn get_vram_region(&self, buffer: &mut [(u8, u8, u8)], rect: Rect) {
+        self.vram.write_region(buffer, rect);
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) {
+        let len = rom.len().min(RAM_SIZE - PGM_OFFSET);
+        self.memory.ram[PGM_OFFSET..PGM_OFFSET + len].copy_from_slice(&rom[..len]);
+    }
+
+    fn set_key_press(&mut self, key: u8, is_down: bool) {
+        self.memory.keys[key as usize & 0xf] = is_down;
+    }
+
+    /// The interpreter sounds the beeper with Q.
+    fn get_sound_timer(&self) -> u8 {
+        self.cpu.q as u8
+    }
+
+    fn reset(&mut self) {
+        self.memory.ram = [0; RAM_SIZE];
+        self.memory.ram[..self.interpreter.len()].copy_from_slice(&self.interpreter);
+        self.memory.display_on = false;
+        self.memory.key_latch = 0;
+        self.cpu = Cdp1802::new();
+        // the interpreter finds the last page of the memory in R1, as left by the monitor.
+        self.cpu.r[1] = (RAM_SIZE as u16 - 1) & 0xff00;
+        self.cycle = 0;
+        self.interrupted = false;
+        self.dma_lines = 0;
+        self.vram = Framebuffer::new();
+    }
+
+    /// The interpreter counts its timers down itself, on the interrupt of each frame.
+    fn tick_timers(&mut self) {}
+
+    /// A frame of the 1861, `ipf` is set by the speed of the 1802.
+    fn run_frame(&mut self, _ipf: u32) -> Frame {
+        let mut ticks = 0;
+        while !self.end_frame() {
+            if self.advance() {
+                ticks += 1;
+            }
+        }
+        Frame {
+            ticks,
+            beep: self.cpu.q,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sets up an interrupt routine showing the memory from 0100 on, turns the display on and
+    // loops, with P = 3 since R0 is the DMA pointer.
+    const PROGRAM: [u8; 0x3b] = [
+        // 00: R3 = 0007; SEP 3
+        0xf8, 0x00, 0xb3, 0xf8, 0x07, 0xa3, 0xd3,
+        // 07: R1 = 0031; R2 = 0fff; SEX 2; INP 1; BR 15
+        0xf8, 0x00, 0xb1, 0xf8, 0x31, 0xa1, 0xf8, 0x0f, 0xb2, 0xf8, 0xff, 0xa2, 0xe2, 0x69, 0x30,
+        0x15, //
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        // 30: RET
+        0x70, //
+        // 31: DEC 2; SAV; R0 = 0100; BR 30
+        0x22, 0x78, 0xf8, 0x01, 0xb0, 0xf8, 0x00, 0xa0, 0x30, 0x30,
+    ];
+
+    #[test]
+    fn test_display() {
+        let mut vip = Vip::new(&PROGRAM, &[]);
+        vip.memory.ram[0x100] = 0xff;
+        vip.memory.ram[0x120] = 0x81;
+        // the display is turned on during the first frame.
+        vip.run_frame(0);
+        vip.take_dirty();
+        let frame = vip.run_frame(0);
+        assert!(frame.ticks > 0);
+        assert_eq!(vip.vram.rows()[0], 0xff << 56);
+        assert_eq!(vip.vram.rows()[1], 0x81 << 56);
+        assert_eq!(vip.take_dirty(), None);
+        // the interrupted program was resumed.
+        assert_eq!((vip.cpu.x, vip.cpu.p, vip.cpu.r[2]), (2, 3, 0x0fff));
+    }
+
+    #[test]
+    fn test_keypad() {
+        let mut vip = Vip::new(&[], &[]);
+        vip.set_key_press(0xa, true);
+        vip.memory.output(2, 0xa);
+        assert!(vip.memory.flag(3));
+        vip.memory.output(2, 0xb);
+        assert!(!vip.memory.flag(3));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::audio::{self, Beeper, Tone};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+pub const WAV_SAMPLE_RATE: u32 = 44100;
+const FRAME_RATE: f64 = 60.0;
+const HEADER_LEN: u32 = 44;
+
+/// Renders the beeper to a 16 bit mono PCM WAV file, one 60Hz frame of sound timer state at a
+/// time, so the output only depends on the emulated frames and not on the audio device.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    beeper: Beeper,
+    samples_per_frame: f64,
+    // fraction of a sample carried over from the previous frame.
+    remainder: f64,
+    samples: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &str, tone: Tone) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), tone)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, tone: Tone) -> io::Result<Self> {
+        // the sizes are patched by `finish`.
+        write_header(&mut out, 0)?;
+        Ok(Self {
+            out,
+            beeper: Beeper::new(tone, WAV_SAMPLE_RATE as i32),
+            samples_per_frame: WAV_SAMPLE_RATE as f64 / FRAME_RATE,
+            remainder: 0.0,
+            samples: 0,
+        })
+    }
+
+    pub fn push_frame(&mut self, beep: bool) -> io::Result<()> {
+        self.beeper.set_gate(beep);
+        self.write_frame(&[])
+    }
+
+    /// Writes a frame of digitised sound, spread over the frame.
+    pub fn push_samples(&mut self, digitised: &[f32]) -> io::Result<()> {
+        self.beeper.set_gate(false);
+        self.write_frame(digitised)
+    }
+
+    fn write_frame(&mut self, digitised: &[f32]) -> io::Result<()> {
+        let samples = self.samples_per_frame + self.remainder;
+        self.remainder = samples.fract();
+        let count = samples as u32;
+        for k in 0..count {
+            let position = k as f64 / count as f64;
+            let sample = self.beeper.next_sample()
+                + audio::resample(digitised, position) * self.beeper.volume();
+            let sample = (sample * i16::MAX as f32) as i16;
+            self.out.write_all(&sample.to_le_bytes())?;
+        }
+        self.samples += count;
+        Ok(())
+    }
+
+    /// The number of samples written so far.
+    pub fn len(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples == 0
+    }
+
+    /// Writes the final sizes in the header and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.out, self.samples * 2)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn write_header<W: Write>(out: &mut W, data_len: u32) -> io::Result<()> {
+    out.write_all(b"RIFF")?;
+    out.write_all(&(HEADER_LEN - 8 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    // PCM, mono
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&WAV_SAMPLE_RATE.to_le_bytes())?;
+    // byte rate and block alignment for 16 bit samples
+    out.write_all(&(WAV_SAMPLE_RATE * 2).to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{Processor, CPU};
+    use std::io::Cursor;
+
+    fn samples(wav: &[u8]) -> Vec<i16> {
+        wav[HEADER_LEN as usize..]
+            .chunks(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn test_wav_header() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), Tone::default()).unwrap();
+        for _ in 0..60 {
+            wav.push_frame(false).unwrap();
+        }
+        assert_eq!(wav.len(), WAV_SAMPLE_RATE);
+        let wav = wav.finish().unwrap().into_inner();
+        assert_eq!(
+            wav.len(),
+            HEADER_LEN as usize + WAV_SAMPLE_RATE as usize * 2
+        );
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[4..8], &(wav.len() as u32 - 8).to_le_bytes());
+        assert_eq!(&wav[40..44], &(WAV_SAMPLE_RATE * 2).to_le_bytes());
+    }
+
+    #[test]
+    fn test_wav_beep_timing() {
+        let mut cpu = CPU::new();
+        // 0x200: LD V0, 30; 0x202: LD ST, V0; 0x204: JMP 0x204
+        cpu.load_rom(&[0x60, 30, 0xf0, 0x18, 0x12, 0x04]);
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), Tone::default()).unwrap();
+        for _ in 0..60 {
+            let frame = cpu.run_frame(10);
+            wav.push_frame(frame.beep).unwrap();
+        }
+        let samples = samples(&wav.finish().unwrap().into_inner());
+        let first = samples.iter().position(|s| *s != 0).unwrap();
+        let last = samples.iter().rposition(|s| *s != 0).unwrap();
+        // the beep lasts half a second, plus the release of the envelope.
+        assert!(first <= 1);
+        assert!((30 * 735..=30 * 735 + 300).contains(&last));
+    }
+}
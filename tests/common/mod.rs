@@ -0,0 +1,73 @@
+use chip_8::cpu::{Processor, CPU};
+use chip_8::headless::{Headless, Screen};
+use chip_8::movie::KeyEvent;
+use chip_8::rng::{Rng, RngModel};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Seed of the random number generator, fixed so that screens are reproducible.
+pub const SEED: u64 = 0xc8;
+
+pub fn rom_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(name)
+}
+
+/// Runs the rom for `frames` frames and returns the screen, or `None` if the rom is missing.
+pub fn run_rom(name: &str, frames: u64, input: Vec<KeyEvent>) -> Option<Screen> {
+    let rom = fs::read(rom_path(name)).ok()?;
+    let mut cpu = CPU::new();
+    cpu.rng = Rng::new(RngModel::Xorshift, SEED);
+    cpu.load_rom(&rom);
+    let mut headless = Headless::new(cpu, chip_8::config::DEFAULT_IPF).with_input(input);
+    headless.run_frames(frames);
+    Some(headless.screen())
+}
+
+/// Presses each key for a few frames, starting at `frame`, one after the other.
+pub fn press_keys(frame: u64, keys: &[u8]) -> Vec<KeyEvent> {
+    keys.iter()
+        .enumerate()
+        .flat_map(|(n, &key)| {
+            let frame = frame + n as u64 * 10;
+            vec![
+                KeyEvent {
+                    frame,
+                    key,
+                    down: true,
+                },
+                KeyEvent {
+                    frame: frame + 5,
+                    key,
+                    down: false,
+                },
+            ]
+        })
+        .collect()
+}
+
+/// Compares the screen against `tests/golden/<name>.txt`, or writes it if `UPDATE_GOLDEN` is set.
+pub fn assert_golden(name: &str, screen: &Screen) {
+    let path = rom_path("tests/golden").join(format!("{}.txt", name));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, screen.to_text()).expect("could not write the golden screen");
+        return;
+    }
+    let text = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "missing golden screen {}, run the tests with UPDATE_GOLDEN=1 to create it",
+            path.display()
+        )
+    });
+    let expected = Screen::from_text(&text).expect("invalid golden screen");
+    if let Some(diff) = screen.diff(&expected) {
+        panic!(
+            "{} doesn't match {} (hash {:016x}, expected {:016x}), {}",
+            name,
+            path.display(),
+            screen.hash(),
+            expected.hash(),
+            diff
+        );
+    }
+}
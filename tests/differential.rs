@@ -0,0 +1,123 @@
+mod reference;
+
+use chip_8::cpu::{Processor, CPU};
+use chip_8::quirks::Quirks;
+use chip_8::rng::{Rng, RngModel};
+use proptest::prelude::*;
+use reference::Reference;
+
+/// Instruction templates: the fixed bits, and the mask of the bits that are random.
+const TEMPLATES: &[(u16, u16)] = &[
+    (0x00e0, 0x0000),
+    (0x00ee, 0x0000),
+    (0x1000, 0x0fff),
+    (0x2000, 0x0fff),
+    (0x3000, 0x0fff),
+    (0x4000, 0x0fff),
+    (0x5000, 0x0ff0),
+    (0x6000, 0x0fff),
+    (0x7000, 0x0fff),
+    (0x8000, 0x0ff7),
+    (0x800e, 0x0ff0),
+    (0x9000, 0x0ff0),
+    (0xa000, 0x0fff),
+    (0xb000, 0x0fff),
+    (0xc000, 0x0fff),
+    (0xd000, 0x0fff),
+    (0xe09e, 0x0f00),
+    (0xe0a1, 0x0f00),
+    (0xf007, 0x0f00),
+    (0xf00a, 0x0f00),
+    (0xf015, 0x0f00),
+    (0xf018, 0x0f00),
+    (0xf01e, 0x0f00),
+    (0xf029, 0x0f00),
+    (0xf030, 0x0f00),
+    (0xf033, 0x0f00),
+    (0xf055, 0x0f00),
+    (0xf065, 0x0f00),
+    (0xf075, 0x0f00),
+    (0xf085, 0x0f00),
+    // anything, including invalid instructions
+    (0x0000, 0xffff),
+];
+
+fn instruction() -> impl Strategy<Value = u16> {
+    (0..TEMPLATES.len(), any::<u16>()).prop_map(|(t, bits)| {
+        let (fixed, mask) = TEMPLATES[t];
+        fixed | (bits & mask)
+    })
+}
+
+/// An instruction, and the keys that are held while it runs.
+fn step() -> impl Strategy<Value = (u16, u16)> {
+    (instruction(), prop_oneof![Just(0), any::<u16>()])
+}
+
+fn assert_same(cpu: &CPU, reference: &Reference, op: u16) -> Result<(), TestCaseError> {
+    prop_assert_eq!(cpu.pc, reference.pc, "pc after {:04x}", op);
+    prop_assert_eq!(cpu.v, reference.v, "v after {:04x}", op);
+    prop_assert_eq!(cpu.i, reference.i, "i after {:04x}", op);
+    prop_assert_eq!(&cpu.stack, &reference.stack, "stack after {:04x}", op);
+    prop_assert_eq!(cpu.delay, reference.delay, "delay after {:04x}", op);
+    prop_assert_eq!(cpu.sound, reference.sound, "sound after {:04x}", op);
+    prop_assert!(cpu.ram[..] == reference.ram[..], "ram after {:04x}", op);
+    prop_assert!(
+        cpu.vram.pixels().eq(reference.vram.iter().copied()),
+        "vram after {:04x}",
+        op
+    );
+    Ok(())
+}
+
+fn run(
+    quirks: Quirks,
+    decode_cache: bool,
+    seed: u64,
+    rom: &[u8],
+    steps: &[(u16, u16)],
+) -> Result<(), TestCaseError> {
+    let mut cpu = CPU::with_quirks(quirks);
+    cpu.set_decode_cache(decode_cache);
+    cpu.rng = Rng::new(RngModel::Xorshift, seed);
+    cpu.load_rom(rom);
+    let mut reference = Reference::new(cpu.rng.clone(), &cpu.ram);
+
+    for &(op, keys) in steps {
+        // the instruction stream is written wherever the pc is.
+        for (n, byte) in op.to_be_bytes().iter().enumerate() {
+            cpu.poke(cpu.pc + n, *byte);
+            reference.ram[(reference.pc + n) % 4096] = *byte;
+        }
+        for key in 0..16 {
+            let down = keys & (1 << key) != 0;
+            cpu.set_key_press(key, down);
+            reference.keys[key as usize] = down;
+        }
+
+        cpu.tick();
+        // with display_wait, a draw only completes after the vertical blank.
+        if cpu.waiting_vblank() {
+            cpu.vblank();
+            cpu.tick();
+        }
+        reference.step();
+        assert_same(&cpu, &reference, op)?;
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn cpu_matches_reference(
+        seed in any::<u64>(),
+        rom in prop::collection::vec(any::<u8>(), 0..256),
+        steps in prop::collection::vec(step(), 1..200),
+    ) {
+        for (_, quirks) in Quirks::presets().iter() {
+            for &decode_cache in &[false, true] {
+                run(*quirks, decode_cache, seed, &rom, &steps)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn golden_maze() {
+    let screen = run_rom("Maze [David Winter, 199x].ch8", 120, Vec::new()).unwrap();
+    assert_golden("maze", &screen);
+}
+
+#[test]
+fn golden_stars() {
+    let screen = run_rom("Stars [Sergey Naydenov, 2010].ch8", 120, Vec::new()).unwrap();
+    assert_golden("stars", &screen);
+}
+
+#[test]
+fn golden_trip8() {
+    let screen = run_rom("Trip8 Demo (2008) [Revival Studios].ch8", 300, Vec::new()).unwrap();
+    assert_golden("trip8", &screen);
+}
+
+#[test]
+fn golden_15_puzzle() {
+    let input = press_keys(60, &[0x4, 0x6, 0x1, 0xc]);
+    let screen = run_rom("15 Puzzle [Roger Ivie] (alt).ch8", 180, input).unwrap();
+    assert_golden("15_puzzle", &screen);
+}
+
+#[test]
+fn golden_keypad_test() {
+    let input = press_keys(30, &[0x0, 0x5, 0xa, 0xf]);
+    let screen = run_rom("Keypad Test [Hap, 2006].ch8", 120, input).unwrap();
+    assert_golden("keypad_test", &screen);
+}
+
+// Timendus' chip8-test-suite (https://github.com/Timendus/chip8-test-suite) isn't distributed
+// with the sources yet, nor are the golden screens of its roms: put the roms in tests/roms and run
+// these tests with --ignored, and UPDATE_GOLDEN=1 the first time. The screens are only as right as
+// the emulator that drew them, check by eye that every test shows its pass mark before keeping
+// them. Until the roms are vendored, these tests aren't run by `cargo test`.
+
+fn suite(name: &str, frames: u64, input: Vec<chip_8::movie::KeyEvent>) {
+    let screen = run_rom(&format!("tests/roms/{}.ch8", name), frames, input)
+        .unwrap_or_else(|| panic!("tests/roms/{}.ch8 is missing", name));
+    assert_golden(name, &screen);
+}
+
+#[test]
+#[ignore = "needs the chip8-test-suite roms in tests/roms"]
+fn suite_chip8_logo() {
+    suite("1-chip8-logo", 60, Vec::new());
+}
+
+#[test]
+#[ignore = "needs the chip8-test-suite roms in tests/roms"]
+fn suite_ibm_logo() {
+    suite("2-ibm-logo", 60, Vec::new());
+}
+
+#[test]
+#[ignore = "needs the chip8-test-suite roms in tests/roms"]
+fn suite_corax_plus() {
+    suite("3-corax+", 120, Vec::new());
+}
+
+#[test]
+#[ignore = "needs the chip8-test-suite roms in tests/roms"]
+fn suite_flags() {
+    suite("4-flags", 240, Vec::new());
+}
+
+#[test]
+#[ignore = "needs the chip8-test-suite roms in tests/roms"]
+fn suite_quirks() {
+    // select the CHIP-8 platform in the menu
+    suite("5-quirks", 600, press_keys(30, &[0x1]));
+}
+
+#[test]
+#[ignore = "needs the chip8-test-suite roms in tests/roms"]
+fn suite_keypad() {
+    // select the FX0A test, then press and release a key
+    suite("6-keypad", 180, press_keys(30, &[0x3, 0x7]));
+}
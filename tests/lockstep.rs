@@ -0,0 +1,106 @@
+#![cfg(feature = "recompiler")]
+
+use chip_8::cpu::{Processor, CPU};
+use chip_8::quirks::Quirks;
+use chip_8::recompiler::Recompiler;
+use chip_8::rng::{Rng, RngModel};
+use proptest::prelude::*;
+
+fn new_cpu(quirks: Quirks, seed: u64, rom: &[u8]) -> CPU {
+    let mut cpu = CPU::with_quirks(quirks);
+    cpu.rng = Rng::new(RngModel::Xorshift, seed);
+    cpu.load_rom(rom);
+    cpu
+}
+
+fn assert_same(cpu: &CPU, recompiled: &CPU, frame: usize) -> Result<(), TestCaseError> {
+    prop_assert_eq!(cpu.pc, recompiled.pc, "pc at frame {}", frame);
+    prop_assert_eq!(cpu.v, recompiled.v, "v at frame {}", frame);
+    prop_assert_eq!(cpu.i, recompiled.i, "i at frame {}", frame);
+    prop_assert_eq!(&cpu.stack, &recompiled.stack, "stack at frame {}", frame);
+    prop_assert_eq!(cpu.delay, recompiled.delay, "delay at frame {}", frame);
+    prop_assert_eq!(cpu.sound, recompiled.sound, "sound at frame {}", frame);
+    prop_assert_eq!(cpu.vblank, recompiled.vblank, "vblank at frame {}", frame);
+    prop_assert!(cpu.ram[..] == recompiled.ram[..], "ram at frame {}", frame);
+    prop_assert!(
+        cpu.vram.rows() == recompiled.vram.rows(),
+        "vram at frame {}",
+        frame
+    );
+    Ok(())
+}
+
+/// A program of valid instructions, where I and the jumps point into the program so that
+/// stores often rewrite code that was already compiled.
+fn program() -> impl Strategy<Value = Vec<u8>> {
+    let instruction = (any::<u16>(), 0..9).prop_map(|(bits, kind)| {
+        let address = 0x200 | (bits & 0x7f);
+        match kind {
+            0 => 0xa000 | address,
+            1 => 0x1000 | address,
+            2 => 0x2000 | address,
+            3 => 0xf055 | (bits & 0x0f00),
+            4 => 0xf033 | (bits & 0x0f00),
+            5 => 0x00ee,
+            6 => 0x6000 | (bits & 0x0fff),
+            // anything else
+            _ => bits,
+        }
+    });
+    prop::collection::vec(instruction, 1..64).prop_map(|insts| {
+        insts
+            .iter()
+            .flat_map(|inst| inst.to_be_bytes().to_vec())
+            .collect()
+    })
+}
+
+fn rom() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![prop::collection::vec(any::<u8>(), 0..512), program()]
+}
+
+proptest! {
+    // stores into compiled code are rare enough to need more cases than the default.
+    #![proptest_config(ProptestConfig::with_cases(2048))]
+
+    #[test]
+    fn recompiler_matches_interpreter(
+        seed in any::<u64>(),
+        rom in rom(),
+        frames in prop::collection::vec((1..64u32, any::<u16>()), 1..60),
+    ) {
+        for (_, quirks) in Quirks::presets().iter() {
+            let mut cpu = new_cpu(*quirks, seed, &rom);
+            let mut recompiler = Recompiler::new(new_cpu(*quirks, seed, &rom));
+            for (n, &(ipf, keys)) in frames.iter().enumerate() {
+                for key in 0..16 {
+                    cpu.set_key_press(key, keys & (1 << key) != 0);
+                    recompiler.set_key_press(key, keys & (1 << key) != 0);
+                }
+                prop_assert_eq!(cpu.run_frame(ipf), recompiler.run_frame(ipf));
+                assert_same(&cpu, &recompiler.cpu, n)?;
+            }
+        }
+    }
+}
+
+#[test]
+fn recompiler_matches_interpreter_on_roms() {
+    let roms = [
+        "Maze [David Winter, 199x].ch8",
+        "Stars [Sergey Naydenov, 2010].ch8",
+        "Trip8 Demo (2008) [Revival Studios].ch8",
+        "15 Puzzle [Roger Ivie] (alt).ch8",
+        "Keypad Test [Hap, 2006].ch8",
+    ];
+    for name in roms.iter() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(name);
+        let rom = std::fs::read(path).unwrap();
+        let mut cpu = new_cpu(Quirks::default(), 0, &rom);
+        let mut recompiler = Recompiler::new(new_cpu(Quirks::default(), 0, &rom));
+        for frame in 0..600 {
+            assert_eq!(cpu.run_frame(50), recompiler.run_frame(50), "{}", name);
+            assert_same(&cpu, &recompiler.cpu, frame).unwrap();
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! A deliberately simple CHIP-8 interpreter, written from the specification rather than from
+//! `cpu.rs`, used as a model to test the real CPU against.
+
+use chip_8::rng::Rng;
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+#[derive(Clone)]
+pub struct Reference {
+    pub v: [u8; 16],
+    pub i: usize,
+    pub pc: usize,
+    pub stack: Vec<usize>,
+    pub ram: Vec<u8>,
+    pub vram: Vec<bool>,
+    pub delay: u8,
+    pub sound: u8,
+    pub keys: [bool; 16],
+    pub flags: [u8; 16],
+    pub rng: Rng,
+}
+
+impl Reference {
+    pub fn new(rng: Rng, ram: &[u8]) -> Self {
+        Self {
+            v: [0; 16],
+            i: 0,
+            pc: 0x200,
+            stack: Vec::new(),
+            ram: ram.to_vec(),
+            vram: vec![false; WIDTH * HEIGHT],
+            delay: 0,
+            sound: 0,
+            keys: [false; 16],
+            flags: [0; 16],
+            rng,
+        }
+    }
+
+    fn read(&self, address: usize) -> u8 {
+        self.ram[address % 4096]
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        self.ram[address % 4096] = value;
+    }
+
+    /// Executes one instruction. The quirks only change the timing, not the effect of
+    /// instructions, so they aren't modeled.
+    pub fn step(&mut self) {
+        let op = (self.read(self.pc) as usize) << 8 | self.read(self.pc + 1) as usize;
+        let x = (op >> 8) & 0xf;
+        let y = (op >> 4) & 0xf;
+        let n = op & 0xf;
+        let nn = (op & 0xff) as u8;
+        let nnn = op & 0xfff;
+        let vx = self.v[x];
+        let vy = self.v[y];
+        let mut next = self.pc + 2;
+
+        match (op >> 12, n) {
+            _ if op == 0x00e0 => self.vram = vec![false; WIDTH * HEIGHT],
+            _ if op == 0x00ee => {
+                if let Some(pc) = self.stack.pop() {
+                    next = pc;
+                }
+            }
+            (0x1, _) => next = nnn,
+            (0x2, _) if self.stack.len() < 16 => {
+                self.stack.push(next % 4096);
+                next = nnn;
+            }
+            (0x3, _) if vx == nn => next += 2,
+            (0x4, _) if vx != nn => next += 2,
+            (0x5, 0) if vx == vy => next += 2,
+            (0x6, _) => self.v[x] = nn,
+            (0x7, _) => self.v[x] = vx.wrapping_add(nn),
+            (0x8, 0x0) => self.v[x] = vy,
+            (0x8, 0x1) => self.v[x] = vx | vy,
+            (0x8, 0x2) => self.v[x] = vx & vy,
+            (0x8, 0x3) => self.v[x] = vx ^ vy,
+            // the flag is written last, so it wins when x is F.
+            (0x8, 0x4) => {
+                self.v[x] = vx.wrapping_add(vy);
+                self.v[0xf] = (vx as usize + vy as usize > 255) as u8;
+            }
+            (0x8, 0x5) => {
+                self.v[x] = vx.wrapping_sub(vy);
+                self.v[0xf] = (vx >= vy) as u8;
+            }
+            (0x8, 0x6) => {
+                self.v[x] = vx / 2;
+                self.v[0xf] = vx % 2;
+            }
+            (0x8, 0x7) => {
+                self.v[x] = vy.wrapping_sub(vx);
+                self.v[0xf] = (vy >= vx) as u8;
+            }
+            (0x8, 0xe) => {
+                self.v[x] = vx.wrapping_mul(2);
+                self.v[0xf] = vx / 128;
+            }
+            (0x9, 0) if vx != vy => next += 2,
+            (0xa, _) => self.i = nnn,
+            (0xb, _) => next = nnn + self.v[0] as usize,
+            (0xc, _) => self.v[x] = self.rng.next_byte() & nn,
+            (0xd, _) => {
+                let mut collision = 0;
+                for row in 0..n {
+                    let byte = self.read(self.i + row);
+                    for col in 0..8 {
+                        if byte & (0x80 >> col) == 0 {
+                            continue;
+                        }
+                        let px = (vx as usize + col) % WIDTH;
+                        let py = (vy as usize + row) % HEIGHT;
+                        let pixel = &mut self.vram[py * WIDTH + px];
+                        if *pixel {
+                            collision = 1;
+                        }
+                        *pixel = !*pixel;
+                    }
+                }
+                self.v[0xf] = collision;
+            }
+            (0xe, _) if nn == 0x9e && self.keys[vx as usize % 16] => next += 2,
+            (0xe, _) if nn == 0xa1 && !self.keys[vx as usize % 16] => next += 2,
+            (0xf, _) => match nn {
+                0x07 => self.v[x] = self.delay,
+                0x0a => match self.keys.iter().position(|k| *k) {
+                    Some(key) => self.v[x] = key as u8,
+                    None => next = self.pc,
+                },
+                0x15 => self.delay = vx,
+                0x18 => self.sound = vx,
+                0x1e => {
+                    self.i += vx as usize;
+                    self.v[0xf] = (self.i > 0xf00) as u8;
+                }
+                0x29 => self.i = (vx as usize & 0xf) * 5,
+                // the big font follows the 16 small glyphs.
+                0x30 => self.i = 80 + (vx as usize & 0xf) * 10,
+                0x33 => {
+                    self.write(self.i, vx / 100);
+                    self.write(self.i + 1, vx / 10 % 10);
+                    self.write(self.i + 2, vx % 10);
+                }
+                0x55 => {
+                    for r in 0..=x {
+                        self.write(self.i + r, self.v[r]);
+                    }
+                }
+                0x65 => {
+                    for r in 0..=x {
+                        self.v[r] = self.read(self.i + r);
+                    }
+                }
+                0x75 => self.flags[..=x].copy_from_slice(&self.v[..=x]),
+                0x85 => self.v[..=x].copy_from_slice(&self.flags[..=x]),
+                _ => {}
+            },
+            _ => {}
+        }
+        self.pc = next % 4096;
+    }
+}